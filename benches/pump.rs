@@ -0,0 +1,42 @@
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use git_remote_utils::task;
+use tokio::{io, runtime, sync::mpsc};
+
+const TOTAL_SIZE: usize = 8 * 1024 * 1024;
+const BUFFER_SIZES: &[usize] = &[1024, 4 * 1024, 16 * 1024, 64 * 1024];
+
+/// Copies `data` to a sink through `task::input` and `task::output`, as the server does.
+async fn pump(data: &[u8], buffer_size: usize) {
+    let (bytes_tx, bytes_rx) = mpsc::channel::<Arc<BytesMut>>(1);
+    let (res_tx, res_rx) = mpsc::channel(1);
+    let input = task::input(data, buffer_size, bytes_tx, res_rx);
+    let output = task::output(io::sink(), res_tx, bytes_rx);
+    let (input, output) = tokio::join!(input, output);
+    input.unwrap();
+    output.unwrap();
+}
+
+fn bench_pump(c: &mut Criterion) {
+    let runtime = runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let data = vec![0xa5; TOTAL_SIZE];
+
+    let mut group = c.benchmark_group("pump");
+    group.throughput(Throughput::Bytes(TOTAL_SIZE as u64));
+    for &buffer_size in BUFFER_SIZES {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(buffer_size),
+            &buffer_size,
+            |b, &buffer_size| b.to_async(&runtime).iter(|| pump(&data, buffer_size)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_pump);
+criterion_main!(benches);
@@ -0,0 +1,32 @@
+use std::env;
+
+/// Records how the binaries were built, for `--version`.
+fn main() {
+    let target = env::var("TARGET").unwrap();
+    let profile = env::var("PROFILE").unwrap();
+    let static_crt = env::var("CARGO_CFG_TARGET_FEATURE")
+        .map(|features| features.split(',').any(|feature| feature == "crt-static"))
+        .unwrap_or(false);
+    let mut features = env::vars()
+        .filter_map(|(name, _)| {
+            // `default` only enables other features, which are listed themselves
+            if name == "CARGO_FEATURE_DEFAULT" {
+                return None;
+            }
+            name.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect::<Vec<_>>();
+    features.sort();
+
+    let linkage = if static_crt { "static" } else { "dynamic" };
+    let features = if features.is_empty() {
+        "none".into()
+    } else {
+        features.join(", ")
+    };
+    println!("cargo:rustc-env=GRU_BUILD_TARGET={target} ({linkage})");
+    println!("cargo:rustc-env=GRU_BUILD_PROFILE={profile}");
+    println!("cargo:rustc-env=GRU_BUILD_FEATURES={features}");
+    println!("cargo:rerun-if-changed=build.rs");
+}
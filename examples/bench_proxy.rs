@@ -0,0 +1,51 @@
+//! Measures the throughput of the stdio pumps over a Unix socket pair.
+//!
+//! Usage: `cargo run --release --example bench_proxy [TOTAL_MIB] [BUFFER_SIZE...]`
+
+use std::{env, sync::Arc, time::Instant};
+
+use bytes::BytesMut;
+use color_eyre::eyre::{self, WrapErr as _};
+use git_remote_utils::{config::DEFAULT_BUFFER_SIZE, task};
+use tokio::{io, net::UnixStream, sync::mpsc};
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    color_eyre::install()?;
+
+    let mut args = env::args().skip(1);
+    let total_mib = match args.next() {
+        Some(arg) => arg.parse().wrap_err("invalid TOTAL_MIB")?,
+        None => 256,
+    };
+    let mut buffer_sizes = args
+        .map(|arg| arg.parse().wrap_err("invalid BUFFER_SIZE"))
+        .collect::<eyre::Result<Vec<usize>>>()?;
+    if buffer_sizes.is_empty() {
+        buffer_sizes = vec![1024, DEFAULT_BUFFER_SIZE, 16 * 1024, 64 * 1024];
+    }
+
+    let data = Arc::new(vec![0xa5; total_mib * 1024 * 1024]);
+    for buffer_size in buffer_sizes {
+        let (writer, mut reader) = UnixStream::pair()?;
+
+        let start = Instant::now();
+        let data = Arc::clone(&data);
+        let send = tokio::spawn(async move {
+            let (bytes_tx, bytes_rx) = mpsc::channel::<Arc<BytesMut>>(1);
+            let (res_tx, res_rx) = mpsc::channel(1);
+            let input = task::input(&data[..], buffer_size, bytes_tx, res_rx);
+            let output = task::output(writer, res_tx, bytes_rx);
+            let (input, output) = tokio::join!(input, output);
+            input.and(output)
+        });
+        let received = io::copy(&mut reader, &mut io::sink()).await?;
+        send.await??;
+        let elapsed = start.elapsed();
+
+        let mib_per_sec = received as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64();
+        println!("buffer size {buffer_size:>6}: {received} bytes in {elapsed:.2?} ({mib_per_sec:.1} MiB/s)");
+    }
+
+    Ok(())
+}
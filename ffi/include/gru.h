@@ -0,0 +1,56 @@
+/*
+ * C bindings for the git-remote-utils client transport.
+ *
+ * Functions returning NULL or a negative value have failed; the reason is then
+ * available from gru_last_error() on the same thread.
+ */
+#ifndef GRU_H
+#define GRU_H
+
+#include <stddef.h>
+#include <stdint.h>
+#include <sys/types.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Connection to a server. */
+typedef struct GruConnection GruConnection;
+
+/*
+ * Connects to the server at addr ("host:port" or "unix:/path").
+ *
+ * If dial_command is not NULL, the server is reached through the stdio of that
+ * command, with the same tokens as --dial-command. A timeout_ms of 0 waits
+ * forever. Returns NULL on failure.
+ */
+GruConnection *gru_connect(const char *addr, const char *dial_command, uint64_t timeout_ms);
+
+/*
+ * Reads up to len bytes into buf, blocking until some are available.
+ * Returns the number of bytes read, 0 at the end of the stream, or -1.
+ */
+ssize_t gru_read(GruConnection *conn, void *buf, size_t len);
+
+/* Writes all the len bytes of buf. Returns len, or -1. */
+ssize_t gru_write(GruConnection *conn, const void *buf, size_t len);
+
+/*
+ * Closes the connection and frees it, killing the dial command if there is
+ * one. Returns 0, or -1 if it could not be shut down cleanly; conn is freed
+ * either way.
+ */
+int gru_close(GruConnection *conn);
+
+/*
+ * Returns the message of the last failure on this thread, or NULL. The string
+ * stays valid until the next failure on this thread.
+ */
+const char *gru_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* GRU_H */
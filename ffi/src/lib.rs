@@ -0,0 +1,210 @@
+//! C bindings for the client transport, declared in `include/gru.h`.
+//!
+//! Functions returning a null pointer or a negative value have failed; the reason is then
+//! available from [`gru_last_error`] on the same thread.
+
+use std::{
+    cell::RefCell,
+    error::Error,
+    ffi::{c_char, c_int, CStr, CString},
+    fmt::Write as _,
+    ptr, slice,
+    time::Duration,
+};
+
+use git_remote_utils::transport::{Connection, Transport};
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    runtime::{self, Runtime},
+};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Connection to a server, opaque to C.
+pub struct GruConnection {
+    connection: Connection,
+    // dropped after the connection, which is registered with it
+    runtime: Runtime,
+}
+
+fn set_last_error(error: &dyn Error) {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        let _ = write!(message, ": {error}");
+        source = error.source();
+    }
+    let message = CString::new(message.replace('\0', "\\0")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+fn set_last_error_message(message: &str) {
+    set_last_error(&std::io::Error::other(message.to_owned()));
+}
+
+/// Reads a string argument, or `None` if it's null.
+///
+/// # Safety
+///
+/// `s` must be null or point to a NUL-terminated string.
+unsafe fn str_arg<'a>(s: *const c_char, name: &str) -> Result<Option<&'a str>, ()> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Ok(Some(s)),
+        Err(e) => {
+            set_last_error_message(&format!("{name} is not valid UTF-8: {e}"));
+            Err(())
+        }
+    }
+}
+
+/// Connects to the server at `addr`.
+///
+/// If `dial_command` isn't null, the server is reached through the stdio of that command, as
+/// with `--dial-command`. A `timeout_ms` of 0 waits forever.
+///
+/// Returns null on failure.
+///
+/// # Safety
+///
+/// `addr` must point to a NUL-terminated string, and `dial_command` must be null or point to one.
+#[no_mangle]
+pub unsafe extern "C" fn gru_connect(
+    addr: *const c_char,
+    dial_command: *const c_char,
+    timeout_ms: u64,
+) -> *mut GruConnection {
+    let addr = match str_arg(addr, "addr") {
+        Ok(Some(addr)) => addr,
+        Ok(None) => {
+            set_last_error_message("addr is null");
+            return ptr::null_mut();
+        }
+        Err(()) => return ptr::null_mut(),
+    };
+    let Ok(dial_command) = str_arg(dial_command, "dial_command") else {
+        return ptr::null_mut();
+    };
+
+    let runtime = match runtime::Builder::new_current_thread().enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            set_last_error(&e);
+            return ptr::null_mut();
+        }
+    };
+    let mut transport = Transport::builder();
+    if let Some(dial_command) = dial_command {
+        transport = transport.dial_command(dial_command);
+    }
+    if timeout_ms > 0 {
+        transport = transport.timeout(Duration::from_millis(timeout_ms));
+    }
+    match runtime.block_on(transport.connect(addr)) {
+        Ok(connection) => Box::into_raw(Box::new(GruConnection {
+            connection,
+            runtime,
+        })),
+        Err(e) => {
+            set_last_error(&e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Reads up to `len` bytes into `buf`, blocking until some are available.
+///
+/// Returns the number of bytes read, 0 at the end of the stream, or -1 on failure.
+///
+/// # Safety
+///
+/// `conn` must come from [`gru_connect`] and not be closed yet, and `buf` must be valid for
+/// writes of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn gru_read(conn: *mut GruConnection, buf: *mut u8, len: usize) -> isize {
+    let Some(conn) = conn.as_mut() else {
+        set_last_error_message("conn is null");
+        return -1;
+    };
+    if len == 0 {
+        return 0;
+    }
+    let buf = slice::from_raw_parts_mut(buf, len);
+    match conn.runtime.block_on(conn.connection.read.read(buf)) {
+        Ok(n) => n as isize,
+        Err(e) => {
+            set_last_error(&e);
+            -1
+        }
+    }
+}
+
+/// Writes all the `len` bytes of `buf`.
+///
+/// Returns `len`, or -1 on failure.
+///
+/// # Safety
+///
+/// `conn` must come from [`gru_connect`] and not be closed yet, and `buf` must be valid for
+/// reads of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn gru_write(conn: *mut GruConnection, buf: *const u8, len: usize) -> isize {
+    let Some(conn) = conn.as_mut() else {
+        set_last_error_message("conn is null");
+        return -1;
+    };
+    if len == 0 {
+        return 0;
+    }
+    let buf = slice::from_raw_parts(buf, len);
+    let write = &mut conn.connection.write;
+    match conn.runtime.block_on(async {
+        write.write_all(buf).await?;
+        write.flush().await
+    }) {
+        Ok(()) => len as isize,
+        Err(e) => {
+            set_last_error(&e);
+            -1
+        }
+    }
+}
+
+/// Closes the connection and frees it, killing the dial command if there is one.
+///
+/// Returns 0, or -1 if the connection could not be shut down cleanly; it is freed either way.
+///
+/// # Safety
+///
+/// `conn` must be null or come from [`gru_connect`], and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn gru_close(conn: *mut GruConnection) -> c_int {
+    if conn.is_null() {
+        return 0;
+    }
+    let mut conn = Box::from_raw(conn);
+    let write = &mut conn.connection.write;
+    match conn.runtime.block_on(write.shutdown()) {
+        Ok(()) => 0,
+        Err(e) => {
+            set_last_error(&e);
+            -1
+        }
+    }
+}
+
+/// Returns the message of the last failure on this thread, or null if nothing has failed.
+///
+/// The string is owned by the library and stays valid until the next failure on this thread.
+#[no_mangle]
+pub extern "C" fn gru_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
@@ -0,0 +1,97 @@
+use std::{
+    ffi::{CStr, CString},
+    fs,
+    io::{Read as _, Write as _},
+    os::unix::net::UnixListener,
+    ptr, thread,
+};
+
+use gru::{gru_close, gru_connect, gru_last_error, gru_read, gru_write, GruConnection};
+
+fn last_error() -> String {
+    let message = gru_last_error();
+    assert!(!message.is_null());
+    unsafe { CStr::from_ptr(message) }
+        .to_str()
+        .unwrap()
+        .to_owned()
+}
+
+fn exchange(conn: *mut GruConnection, data: &[u8]) -> Vec<u8> {
+    assert_eq!(
+        unsafe { gru_write(conn, data.as_ptr(), data.len()) },
+        data.len() as isize
+    );
+    let mut received = vec![0; data.len()];
+    let mut filled = 0;
+    while filled < received.len() {
+        let buf = &mut received[filled..];
+        let n = unsafe { gru_read(conn, buf.as_mut_ptr(), buf.len()) };
+        assert!(n > 0, "{n}");
+        filled += n as usize;
+    }
+    received
+}
+
+#[test]
+fn socket_connection_is_read_and_written() {
+    let socket = std::env::temp_dir().join(format!("gru-ffi-{}.sock", std::process::id()));
+    let _ = fs::remove_file(&socket);
+    let listener = UnixListener::bind(&socket).unwrap();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut data = [0; 5];
+        stream.read_exact(&mut data).unwrap();
+        stream.write_all(&data.to_ascii_uppercase()).unwrap();
+        // closing the connection shuts down its write half
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).unwrap();
+        rest
+    });
+
+    let addr = CString::new(format!("unix:{}", socket.display())).unwrap();
+    let conn = unsafe { gru_connect(addr.as_ptr(), ptr::null(), 1000) };
+    assert!(!conn.is_null(), "{}", last_error());
+    assert_eq!(exchange(conn, b"hello"), b"HELLO");
+    assert_eq!(unsafe { gru_close(conn) }, 0);
+    assert!(server.join().unwrap().is_empty());
+    fs::remove_file(&socket).unwrap();
+}
+
+#[test]
+fn dial_command_stdio_is_the_connection() {
+    let addr = CString::new("example.com:9419").unwrap();
+    let command = CString::new("cat").unwrap();
+    let conn = unsafe { gru_connect(addr.as_ptr(), command.as_ptr(), 0) };
+    assert!(!conn.is_null(), "{}", last_error());
+    assert_eq!(exchange(conn, b"hello"), b"hello");
+    assert_eq!(exchange(conn, &[0xff; 100_000]), [0xff; 100_000]);
+    assert_eq!(unsafe { gru_close(conn) }, 0);
+}
+
+#[test]
+fn failures_are_reported_as_strings() {
+    let addr = CString::new("unix:/nonexistent/gru.sock").unwrap();
+    let conn = unsafe { gru_connect(addr.as_ptr(), ptr::null(), 0) };
+    assert!(conn.is_null());
+    let message = last_error();
+    assert!(
+        message.starts_with("failed to connect socket: unix:/nonexistent/gru.sock: "),
+        "{message}"
+    );
+
+    let conn = unsafe { gru_connect(ptr::null(), ptr::null(), 0) };
+    assert!(conn.is_null());
+    assert_eq!(last_error(), "addr is null");
+
+    let command = CString::new("%x").unwrap();
+    let conn = unsafe { gru_connect(addr.as_ptr(), command.as_ptr(), 0) };
+    assert!(conn.is_null());
+    assert_eq!(
+        last_error(),
+        "failed to spawn dial command: %x: unknown token in dial command: %x"
+    );
+
+    assert_eq!(unsafe { gru_read(ptr::null_mut(), ptr::null_mut(), 1) }, -1);
+    assert_eq!(last_error(), "conn is null");
+}
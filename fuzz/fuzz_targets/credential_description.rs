@@ -0,0 +1,8 @@
+#![no_main]
+
+use git_remote_utils::credential::Description;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &[u8]| {
+    let _ = Description::parse(input).url();
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use git_remote_utils::dial;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (&str, &str)| {
+    let (template, addr) = input;
+    let _ = dial::expand(template, addr);
+});
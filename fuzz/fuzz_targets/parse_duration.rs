@@ -0,0 +1,8 @@
+#![no_main]
+
+use git_remote_utils::config;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|value: &str| {
+    let _ = config::parse_duration("fuzz", value.to_owned());
+});
@@ -0,0 +1,27 @@
+#![no_main]
+
+use bytes::BytesMut;
+use git_remote_utils::protocol::{
+    self, AuthResponse, ClientHello, ClientMessage, SessionHello, SpawnMessage,
+    MAX_HANDSHAKE_FRAME_LENGTH,
+};
+use libfuzzer_sys::fuzz_target;
+use tokio_util::codec::Decoder as _;
+
+// frames as read by the server before the client is authenticated
+fuzz_target!(|input: &[u8]| {
+    let mut codec = protocol::codec(MAX_HANDSHAKE_FRAME_LENGTH);
+    let mut buf = BytesMut::from(input);
+    while let Ok(Some(frame)) = codec.decode(&mut buf) {
+        match protocol::decode_hello::<ClientHello>(&frame) {
+            Some(_) => {
+                let _ = protocol::decode_hello::<SessionHello>(&frame);
+                let _ = protocol::decode_hello::<AuthResponse>(&frame);
+            }
+            None => {
+                let _ = protocol::decode::<SpawnMessage>(&frame);
+                let _ = protocol::decode::<ClientMessage>(&frame);
+            }
+        }
+    }
+});
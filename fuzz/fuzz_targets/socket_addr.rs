@@ -0,0 +1,10 @@
+#![no_main]
+
+use git_remote_utils::socket::SocketAddr;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|addr: &str| {
+    if let Some(Ok(addr)) = SocketAddr::parse_unix(addr) {
+        let _ = addr.to_string();
+    }
+});
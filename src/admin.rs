@@ -0,0 +1,374 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::Notify,
+    time,
+};
+use tokio_util::sync::CancellationToken;
+use ulid::Ulid;
+
+use crate::{
+    audit::AuditWriter,
+    event::{Event, EventQueue},
+    metrics::Metrics,
+    protocol::{Command, Exit, Priority},
+    task::TaskSet,
+};
+
+/// Request sent to the admin socket, one JSON object per line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    ListSessions,
+    KillSession { id: u64 },
+    Stats,
+}
+
+/// Response to a [`Request`], one JSON object per line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum Response {
+    Sessions {
+        sessions: Vec<SessionInfo>,
+    },
+    Killed {
+        id: u64,
+    },
+    Stats {
+        counters: BTreeMap<String, u64>,
+        gauges: BTreeMap<String, i64>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// State of a live session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: u64,
+    /// Identifier shared with the client's logs, once the handshake is done
+    pub session_id: Option<Ulid>,
+    pub peer: String,
+    /// URL of the remote the credential is for, if the client sent it
+    pub url: Option<String>,
+    /// Key the client authenticated with, as a fingerprint followed by the key's comment
+    pub key: Option<String>,
+    /// Requested command, once received
+    pub command: Option<Command>,
+    pub priority: Option<Priority>,
+    /// Process id of the spawned command
+    pub pid: Option<u32>,
+    /// How the command exited, once it has
+    pub exit: Option<Exit>,
+    /// Bytes received from the client (the command's stdin)
+    pub bytes_received: u64,
+    /// Bytes sent to the client (the command's stdout and stderr)
+    pub bytes_sent: u64,
+    /// Start time, in seconds since the Unix epoch
+    pub started_at: u64,
+    /// Time since the start, in seconds
+    pub duration: f64,
+}
+
+/// Registry of the sessions running in the server.
+#[derive(Debug, Default)]
+pub struct Sessions {
+    entries: Mutex<HashMap<u64, Entry>>,
+    /// End of the last session
+    last_ended: Mutex<Option<Instant>>,
+    /// Notified when a session is registered or removed
+    changed: Notify,
+    audit_log: Option<AuditWriter>,
+    events: Option<EventQueue>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    info: SessionInfo,
+    started: Instant,
+    cancel: CancellationToken,
+    traffic: Arc<Traffic>,
+}
+
+impl Entry {
+    /// Current state of the session.
+    fn info(&self) -> SessionInfo {
+        SessionInfo {
+            bytes_received: self.traffic.received.load(Ordering::Relaxed),
+            bytes_sent: self.traffic.sent.load(Ordering::Relaxed),
+            duration: self.started.elapsed().as_secs_f64(),
+            ..self.info.clone()
+        }
+    }
+}
+
+/// Bytes transferred by a session, updated without locking the registry.
+#[derive(Debug, Default)]
+pub struct Traffic {
+    received: AtomicU64,
+    sent: AtomicU64,
+}
+
+impl Traffic {
+    pub fn add_received(&self, bytes: usize) {
+        self.received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_sent(&self, bytes: usize) {
+        self.sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+/// Registration of a session, removed from the registry on drop.
+#[derive(Debug)]
+pub struct SessionHandle {
+    sessions: Arc<Sessions>,
+    id: u64,
+    cancel: CancellationToken,
+    traffic: Arc<Traffic>,
+}
+
+impl Sessions {
+    /// Records each finished session through `audit_log`.
+    pub fn with_audit_log(self, audit_log: AuditWriter) -> Self {
+        Self {
+            audit_log: Some(audit_log),
+            ..self
+        }
+    }
+
+    /// Sends the lifecycle events of the sessions to `events`.
+    pub fn with_events(self, events: EventQueue) -> Self {
+        Self {
+            events: Some(events),
+            ..self
+        }
+    }
+
+    pub fn register(self: &Arc<Self>, id: u64, peer: String) -> SessionHandle {
+        let cancel = CancellationToken::new();
+        let traffic = Arc::new(Traffic::default());
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let entry = Entry {
+            info: SessionInfo {
+                id,
+                session_id: None,
+                peer,
+                url: None,
+                key: None,
+                command: None,
+                priority: None,
+                pid: None,
+                exit: None,
+                bytes_received: 0,
+                bytes_sent: 0,
+                started_at,
+                duration: 0.0,
+            },
+            started: Instant::now(),
+            cancel: cancel.clone(),
+            traffic: Arc::clone(&traffic),
+        };
+        self.entries.lock().unwrap().insert(id, entry);
+        self.changed.notify_waiters();
+        SessionHandle {
+            sessions: Arc::clone(self),
+            id,
+            cancel,
+            traffic,
+        }
+    }
+
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let entries = self.entries.lock().unwrap();
+        let mut sessions = entries.values().map(Entry::info).collect::<Vec<_>>();
+        sessions.sort_by_key(|session| session.id);
+        sessions
+    }
+
+    /// Waits until no session has been running for `timeout`.
+    ///
+    /// Time before the call doesn't count as idle.
+    pub async fn wait_idle(&self, timeout: Duration) {
+        let start = Instant::now();
+        loop {
+            // created before checking, so that changes made in between are not missed
+            let changed = self.changed.notified();
+            let idle_since = self.entries.lock().unwrap().is_empty().then(|| {
+                self.last_ended
+                    .lock()
+                    .unwrap()
+                    .map_or(start, |ended| ended.max(start))
+            });
+            match idle_since {
+                Some(since) => tokio::select! {
+                    () = time::sleep_until((since + timeout).into()) => return,
+                    () = changed => {}
+                },
+                None => changed.await,
+            }
+        }
+    }
+
+    /// Requests the session to terminate, returning whether it exists.
+    pub fn kill(&self, id: u64) -> bool {
+        match self.entries.lock().unwrap().get(&id) {
+            Some(entry) => {
+                entry.cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl SessionHandle {
+    /// Token cancelled when the session is killed through the admin socket.
+    pub fn cancel_token(&self) -> &CancellationToken {
+        &self.cancel
+    }
+
+    pub fn traffic(&self) -> &Arc<Traffic> {
+        &self.traffic
+    }
+
+    pub fn update(&self, f: impl FnOnce(&mut SessionInfo)) {
+        if let Some(entry) = self.sessions.entries.lock().unwrap().get_mut(&self.id) {
+            f(&mut entry.info);
+        }
+    }
+
+    /// Emits the event telling that the command of the session has been spawned.
+    pub fn emit_started(&self) {
+        if let Some(events) = &self.sessions.events {
+            if let Some(entry) = self.sessions.entries.lock().unwrap().get(&self.id) {
+                events.send(Event::SessionStarted {
+                    session: entry.info(),
+                });
+            }
+        }
+    }
+}
+
+impl Drop for SessionHandle {
+    fn drop(&mut self) {
+        let info = self
+            .sessions
+            .entries
+            .lock()
+            .unwrap()
+            .remove(&self.id)
+            .map(|entry| entry.info());
+        if let Some(info) = info {
+            if let Some(audit_log) = &self.sessions.audit_log {
+                audit_log.send(info.clone());
+            }
+            if let Some(events) = &self.sessions.events {
+                events.send(Event::ended(info));
+            }
+        }
+        *self.sessions.last_ended.lock().unwrap() = Some(Instant::now());
+        self.sessions.changed.notify_waiters();
+    }
+}
+
+/// Answers admin requests on `listener` forever.
+pub async fn serve(listener: UnixListener, sessions: Arc<Sessions>, metrics: Arc<Metrics>) {
+    // requests being answered, aborted when the listener is
+    let mut requests = TaskSet::default();
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            Some(_) = requests.join_next(), if !requests.is_empty() => continue,
+        };
+        match accepted {
+            Ok((stream, _addr)) => {
+                let sessions = Arc::clone(&sessions);
+                let metrics = Arc::clone(&metrics);
+                requests.spawn(async move {
+                    if let Err(e) = handle(stream, &sessions, &metrics).await {
+                        tracing::debug!("failed to answer admin request: {e}");
+                    }
+                });
+            }
+            Err(e) => tracing::info!("failed to accept admin connection: {e}"),
+        }
+    }
+}
+
+async fn handle(stream: UnixStream, sessions: &Sessions, metrics: &Metrics) -> io::Result<()> {
+    let (read_stream, mut write_stream) = stream.into_split();
+    let mut lines = BufReader::new(read_stream).lines();
+    while let Some(line) = lines.next_line().await? {
+        let response = match serde_json::from_str(&line) {
+            Ok(request) => respond(request, sessions, metrics),
+            Err(e) => Response::Error {
+                message: format!("invalid request: {e}"),
+            },
+        };
+        let mut response = serde_json::to_vec(&response)?;
+        response.push(b'\n');
+        write_stream.write_all(&response).await?;
+    }
+    Ok(())
+}
+
+fn respond(request: Request, sessions: &Sessions, metrics: &Metrics) -> Response {
+    tracing::info!("admin request: {request:?}");
+    match request {
+        Request::ListSessions => Response::Sessions {
+            sessions: sessions.list(),
+        },
+        Request::KillSession { id } if sessions.kill(id) => Response::Killed { id },
+        Request::KillSession { id } => Response::Error {
+            message: format!("no such session: {id}"),
+        },
+        Request::Stats => {
+            let snapshot = metrics.snapshot();
+            Response::Stats {
+                counters: snapshot
+                    .counters
+                    .into_iter()
+                    .map(|(name, value)| (name.to_owned(), value))
+                    .collect(),
+                gauges: snapshot
+                    .gauges
+                    .into_iter()
+                    .map(|(name, value)| (name.to_owned(), value))
+                    .collect(),
+            }
+        }
+    }
+}
+
+/// Sends a single request to the admin socket at `path`.
+pub async fn request(path: &Path, request: &Request) -> io::Result<Response> {
+    let stream = UnixStream::connect(path).await?;
+    let (read_stream, mut write_stream) = stream.into_split();
+    let mut line = serde_json::to_vec(request)?;
+    line.push(b'\n');
+    write_stream.write_all(&line).await?;
+    write_stream.shutdown().await?;
+
+    let line = BufReader::new(read_stream)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "server sent no response"))?;
+    Ok(serde_json::from_str(&line)?)
+}
@@ -0,0 +1,144 @@
+use std::{env, io, path::Path};
+
+use ssh_key::{Algorithm, PublicKey, Signature};
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::UnixStream,
+};
+
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+const SSH_AGENT_RSA_SHA2_256: u32 = 2;
+
+/// Largest reply accepted from the agent.
+const MAX_MESSAGE_LEN: usize = 256 * 1024;
+
+/// Client of an ssh-agent, speaking the protocol of `draft-miller-ssh-agent`.
+#[derive(Debug)]
+pub struct Agent {
+    stream: UnixStream,
+}
+
+impl Agent {
+    /// Connects to the agent at `SSH_AUTH_SOCK`.
+    pub async fn connect_env() -> io::Result<Self> {
+        let path = env::var_os("SSH_AUTH_SOCK")
+            .filter(|path| !path.is_empty())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "SSH_AUTH_SOCK is not set"))?;
+        Self::connect(Path::new(&path)).await
+    }
+
+    pub async fn connect(path: &Path) -> io::Result<Self> {
+        let stream = UnixStream::connect(path).await?;
+        Ok(Self { stream })
+    }
+
+    /// Lists the public keys held by the agent, with their comments.
+    pub async fn identities(&mut self) -> io::Result<Vec<PublicKey>> {
+        let reply = self.request(&[SSH_AGENTC_REQUEST_IDENTITIES]).await?;
+        let mut reader = Reader(&reply);
+        expect_type(&mut reader, SSH_AGENT_IDENTITIES_ANSWER)?;
+        let count = reader.u32()?;
+        let mut keys = Vec::new();
+        for _ in 0..count {
+            let blob = reader.string()?;
+            let comment = reader.string()?;
+            let mut key = PublicKey::from_bytes(blob).map_err(invalid_data)?;
+            key.set_comment(String::from_utf8_lossy(comment));
+            keys.push(key);
+        }
+        Ok(keys)
+    }
+
+    /// Asks the agent to sign `data` with the private key of `key`.
+    ///
+    /// RSA keys sign with SHA-256, as the SHA-1 based `ssh-rsa` signatures are widely disabled.
+    pub async fn sign(&mut self, key: &PublicKey, data: &[u8]) -> io::Result<Signature> {
+        let blob = key.to_bytes().map_err(invalid_data)?;
+        let flags = match key.algorithm() {
+            Algorithm::Rsa { .. } => SSH_AGENT_RSA_SHA2_256,
+            _ => 0,
+        };
+        let mut msg = vec![SSH_AGENTC_SIGN_REQUEST];
+        put_string(&mut msg, &blob);
+        put_string(&mut msg, data);
+        msg.extend_from_slice(&flags.to_be_bytes());
+
+        let reply = self.request(&msg).await?;
+        let mut reader = Reader(&reply);
+        expect_type(&mut reader, SSH_AGENT_SIGN_RESPONSE)?;
+        Signature::try_from(reader.string()?).map_err(invalid_data)
+    }
+
+    async fn request(&mut self, msg: &[u8]) -> io::Result<Vec<u8>> {
+        let len = u32::try_from(msg.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "message too long"))?;
+        self.stream.write_all(&len.to_be_bytes()).await?;
+        self.stream.write_all(msg).await?;
+
+        let len = self.stream.read_u32().await? as usize;
+        if len == 0 || len > MAX_MESSAGE_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid agent message length: {len}"),
+            ));
+        }
+        let mut reply = vec![0; len];
+        self.stream.read_exact(&mut reply).await?;
+        Ok(reply)
+    }
+}
+
+fn expect_type(reader: &mut Reader<'_>, expected: u8) -> io::Result<()> {
+    match reader.u8()? {
+        ty if ty == expected => Ok(()),
+        SSH_AGENT_FAILURE => Err(io::Error::other("agent refused the request")),
+        ty => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected agent message type: {ty}"),
+        )),
+    }
+}
+
+fn put_string(buf: &mut Vec<u8>, data: &[u8]) {
+    // callers only pass keys and challenges, far below 4 GiB
+    buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buf.extend_from_slice(data);
+}
+
+fn invalid_data(e: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Reader of the SSH wire encoding (RFC 4251).
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated agent message",
+            ));
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+}
@@ -0,0 +1,273 @@
+use std::{
+    fmt::Write as _,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Seek as _, Write as _},
+    os::unix::prelude::{AsRawFd, RawFd},
+    path::Path,
+    sync::Mutex,
+    thread,
+};
+
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest as _, Sha256};
+use tokio::sync::mpsc;
+
+use crate::admin::SessionInfo;
+
+/// `prev` of the first record of a log.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuditError {
+    #[error("failed to read audit log")]
+    Io(#[from] io::Error),
+    #[error("line {line}: invalid record")]
+    Invalid {
+        line: usize,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("line {line}: record has no hash")]
+    MissingHash { line: usize },
+    #[error("line {line}: hash doesn't match the record, which was altered")]
+    HashMismatch { line: usize },
+    #[error("line {line}: record doesn't follow the previous one")]
+    BrokenChain { line: usize },
+}
+
+/// Log of finished sessions, one JSON object per line.
+///
+/// Each record holds the SHA-256 hash of the previous one in `prev` and its own in `hash`, so
+/// altering, removing or reordering records breaks the chain, see [`verify`]. `hash` is the last
+/// field of the line and covers the exact bytes of the line without it, so that verifying doesn't
+/// depend on numbers being formatted again the same way. Removing records
+/// from the end can only be detected by comparing the last hash with a copy kept elsewhere.
+#[derive(Debug)]
+pub struct AuditLog {
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    file: File,
+    last_hash: String,
+    /// Length of the log after the last record written by this process
+    len: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Record<'a> {
+    #[serde(flatten)]
+    session: &'a SessionInfo,
+    prev: &'a str,
+}
+
+impl AuditLog {
+    /// Opens the log at `path` for appending, continuing the chain of the records already in it.
+    pub fn open(path: &Path) -> Result<Self, AuditError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        Self::from_file(file)
+    }
+
+    /// Continues the log opened as `file` for reading and appending, such as one handed over by
+    /// the server this one replaces.
+    pub fn from_file(file: File) -> Result<Self, AuditError> {
+        let (last_hash, len) = with_lock(&file, || last_record(&file))?;
+        Ok(Self {
+            state: Mutex::new(State {
+                file,
+                last_hash,
+                len,
+            }),
+        })
+    }
+
+    /// Appends the record of a finished session.
+    ///
+    /// The log can be shared with other processes, such as the server this one is handed over
+    /// to: it is locked while the record is written, after the records appended by the others.
+    pub fn append(&self, session: &SessionInfo) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        with_lock(&state.file, || {
+            if state.file.metadata()?.len() != state.len {
+                (state.last_hash, state.len) = last_record(&state.file)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            }
+            let record = Record {
+                session,
+                prev: &state.last_hash,
+            };
+            let mut line = serde_json::to_vec(&record)?;
+            let hash = hash(&line);
+            line.pop();
+            writeln!(line, "{}", hash_field(&hash))?;
+            (&state.file).write_all(&line)?;
+            state.file.sync_data()?;
+            state.last_hash = hash;
+            state.len = state.file.metadata()?.len();
+            Ok(())
+        })
+    }
+}
+
+impl AsRawFd for AuditLog {
+    fn as_raw_fd(&self) -> RawFd {
+        self.state.lock().unwrap().file.as_raw_fd()
+    }
+}
+
+/// Appends the records of finished sessions to an [`AuditLog`] from a dedicated thread, so that
+/// ending a session never waits for the lock or the disk.
+///
+/// Dropping the writer waits until the queued records are written. A record that can't be
+/// written, e.g. because the disk is full, is logged as an error and skipped.
+#[derive(Debug)]
+pub struct AuditWriter {
+    sender: Option<mpsc::UnboundedSender<SessionInfo>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl AuditWriter {
+    /// Starts the thread writing to `log`.
+    pub fn spawn(log: AuditLog) -> io::Result<Self> {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<SessionInfo>();
+        let thread = thread::Builder::new()
+            .name("audit-log".into())
+            .spawn(move || {
+                while let Some(session) = receiver.blocking_recv() {
+                    if let Err(e) = log.append(&session) {
+                        tracing::error!("failed to write audit log: {e}");
+                    }
+                }
+            })?;
+        Ok(Self {
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+
+    /// Queues the record of a finished session.
+    pub fn send(&self, session: SessionInfo) {
+        // the thread runs until the sender is dropped
+        let _ = self.sender.as_ref().unwrap().send(session);
+    }
+}
+
+impl Drop for AuditWriter {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Runs `f` holding an exclusive `fcntl` record lock on the whole of `file`.
+///
+/// Record locks belong to the process, unlike `flock` locks, which belong to the open file
+/// description and so would be shared with a server the log is handed over to. Threads of the
+/// process are serialized by the mutex of [`AuditLog`] instead.
+fn with_lock<T, E>(file: &File, f: impl FnOnce() -> Result<T, E>) -> Result<T, E>
+where
+    E: From<io::Error>,
+{
+    let mut lock = unsafe { std::mem::zeroed::<libc::flock>() };
+    lock.l_type = libc::F_WRLCK as _;
+    lock.l_whence = libc::SEEK_SET as _;
+    // a length of 0 covers the file however long it grows
+    while unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETLKW, &lock) } < 0 {
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err.into());
+        }
+    }
+    let res = f();
+    lock.l_type = libc::F_UNLCK as _;
+    unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETLK, &lock) };
+    res
+}
+
+/// Reads the hash of the last record of the log and its length.
+fn last_record(mut file: &File) -> Result<(String, u64), AuditError> {
+    file.rewind()?;
+    let mut last_hash = GENESIS_HASH.to_owned();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let (_, hash) = parse(&line, index + 1)?;
+        last_hash = hash;
+    }
+    Ok((last_hash, file.metadata()?.len()))
+}
+
+/// Summary of a verified log.
+#[derive(Debug, Clone, Serialize)]
+pub struct Verified {
+    pub records: usize,
+    /// Hash of the last record, to be kept elsewhere to detect later truncation
+    pub last_hash: String,
+}
+
+/// Checks that the records of a log are intact and chained in order.
+pub fn verify(reader: impl BufRead) -> Result<Verified, AuditError> {
+    let mut verified = Verified {
+        records: 0,
+        last_hash: GENESIS_HASH.to_owned(),
+    };
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let line_number = index + 1;
+        let (value, hash) = parse(&line, line_number)?;
+        if value["prev"] != verified.last_hash.as_str() {
+            return Err(AuditError::BrokenChain { line: line_number });
+        }
+        verified.records += 1;
+        verified.last_hash = hash;
+    }
+    Ok(verified)
+}
+
+/// Parses a record, checking its hash, and returns it without the hash.
+fn parse(line: &str, line_number: usize) -> Result<(Value, String), AuditError> {
+    let mut value = serde_json::from_str::<Value>(line).map_err(|source| AuditError::Invalid {
+        line: line_number,
+        source,
+    })?;
+    let hash = value
+        .as_object_mut()
+        .and_then(|record| record.remove("hash"))
+        .and_then(|hash| hash.as_str().map(str::to_owned))
+        .ok_or(AuditError::MissingHash { line: line_number })?;
+    let intact = line
+        .strip_suffix(&hash_field(&hash))
+        .is_some_and(|record| self::hash(format!("{record}}}").as_bytes()) == hash);
+    if !intact {
+        return Err(AuditError::HashMismatch { line: line_number });
+    }
+    Ok((value, hash))
+}
+
+/// End of a line holding `hash`, replacing the closing brace of the hashed record.
+fn hash_field(hash: &str) -> String {
+    format!(",\"hash\":\"{hash}\"}}")
+}
+
+/// Hashes a record serialized without its hash.
+fn hash(record: &[u8]) -> String {
+    let digest = Sha256::digest(record);
+    digest.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
@@ -0,0 +1,306 @@
+use std::{io, path::Path};
+
+use bytes::{Bytes, BytesMut};
+use color_eyre::{
+    eyre::{self, bail, eyre, WrapErr as _},
+    Section as _,
+};
+use futures::{Sink, SinkExt as _, Stream, TryStreamExt as _};
+use signature::Verifier as _;
+use ssh_key::{authorized_keys::Entry, HashAlg, PublicKey, Signature};
+
+use crate::{
+    agent::Agent,
+    metrics::Metrics,
+    protocol::{self, AuthChallenge, AuthReply, AuthResponse},
+};
+
+/// Length of the random challenge sent by the server.
+pub const CHALLENGE_LEN: usize = 32;
+
+/// Number of keys a client may try before the server closes the connection.
+pub const MAX_ATTEMPTS: usize = 6;
+
+/// Prefix of the signed data, so that a signature made for this protocol can't be replayed
+/// elsewhere and the other way around.
+const NAMESPACE: &[u8] = b"gru-credential-helper-auth-v1\0";
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("invalid public key")]
+    InvalidKey(#[source] ssh_key::Error),
+    #[error("key {0} is not authorized")]
+    NotAuthorized(String),
+    #[error("invalid signature from key {0}")]
+    InvalidSignature(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthorizedKeysError {
+    #[error("line {line}: invalid key")]
+    InvalidKey {
+        line: usize,
+        #[source]
+        source: ssh_key::Error,
+    },
+    #[error("line {line}: key options are not supported: {options}")]
+    Options { line: usize, options: String },
+}
+
+/// Generates a new random challenge.
+pub fn challenge() -> io::Result<[u8; CHALLENGE_LEN]> {
+    let mut challenge = [0; CHALLENGE_LEN];
+    let mut filled = 0;
+    while filled < challenge.len() {
+        let rest = &mut challenge[filled..];
+        let ret = unsafe { libc::getrandom(rest.as_mut_ptr().cast(), rest.len(), 0) };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        filled += ret as usize;
+    }
+    Ok(challenge)
+}
+
+/// Data signed by the client to answer `challenge`.
+pub fn signed_data(challenge: &[u8]) -> Vec<u8> {
+    [NAMESPACE, challenge].concat()
+}
+
+/// Human-readable identity of a key, its SHA-256 fingerprint followed by its comment.
+pub fn describe(key: &PublicKey) -> String {
+    let fingerprint = key.fingerprint(HashAlg::Sha256);
+    match key.comment() {
+        "" => fingerprint.to_string(),
+        comment => format!("{fingerprint} {comment}"),
+    }
+}
+
+/// Checks whether `identity` names `key`, by its SHA-256 fingerprint (`SHA256:...`) or its
+/// comment.
+pub fn is_identity(key: &PublicKey, identity: &str) -> bool {
+    key.fingerprint(HashAlg::Sha256).to_string() == identity || key.comment() == identity
+}
+
+/// Keys allowed to use the server, read from an OpenSSH `authorized_keys` file.
+///
+/// Key options such as `from=` or `command=` are not supported: the server couldn't enforce them,
+/// so entries with options are rejected rather than granting more than they say.
+#[derive(Debug, Clone)]
+pub struct AuthorizedKeySet {
+    keys: Vec<PublicKey>,
+}
+
+impl AuthorizedKeySet {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Parses the contents of an `authorized_keys` file.
+    pub fn parse(input: &str) -> Result<Self, AuthorizedKeysError> {
+        let mut keys = Vec::new();
+        for (index, line) in input.lines().enumerate() {
+            // same comment handling as OpenSSH and `ssh_key::AuthorizedKeys`
+            let line = line
+                .split_once('#')
+                .map_or(line, |(line, _)| line)
+                .trim_end();
+            if line.is_empty() {
+                continue;
+            }
+            keys.push(parse_entry(index + 1, line)?);
+        }
+        Ok(Self { keys })
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Checks that `signature` answers `challenge` and was made by an authorized key, returning
+    /// the authorized key.
+    ///
+    /// `public_key` is the key in the SSH wire encoding.
+    pub fn verify(
+        &self,
+        challenge: &[u8],
+        public_key: &[u8],
+        signature: &[u8],
+    ) -> Result<&PublicKey, AuthError> {
+        let key = PublicKey::from_bytes(public_key).map_err(AuthError::InvalidKey)?;
+        let authorized = self
+            .keys
+            .iter()
+            .find(|authorized| authorized.key_data() == key.key_data())
+            .ok_or_else(|| AuthError::NotAuthorized(describe(&key)))?;
+        let valid = Signature::try_from(signature).is_ok_and(|signature| {
+            key.key_data()
+                .verify(&signed_data(challenge), &signature)
+                .is_ok()
+        });
+        if !valid {
+            return Err(AuthError::InvalidSignature(describe(authorized)));
+        }
+        Ok(authorized)
+    }
+}
+
+fn parse_entry(line_number: usize, line: &str) -> Result<PublicKey, AuthorizedKeysError> {
+    let options = |options: &str| AuthorizedKeysError::Options {
+        line: line_number,
+        options: options.into(),
+    };
+    match line.parse::<Entry>() {
+        Ok(entry) if entry.config_opts().is_empty() => Ok(entry.public_key().clone()),
+        Ok(entry) => Err(options(entry.config_opts().as_str())),
+        // `ssh_key` reads `restrict KEY COMMENT` as a key with an invalid algorithm
+        Err(source) => match line.split_once(' ') {
+            Some((opts, key)) if key.parse::<PublicKey>().is_ok() => Err(options(opts)),
+            _ => Err(AuthorizedKeysError::InvalidKey {
+                line: line_number,
+                source,
+            }),
+        },
+    }
+}
+
+/// Has the client sign a challenge with one of `keys`, returning the description of the accepted
+/// key.
+///
+/// This runs on the server after the handshake, when [`protocol::AUTH_FEATURE`] is enabled.
+pub async fn verify_client<R, W>(
+    read_stream: &mut R,
+    write_stream: &mut W,
+    keys: &AuthorizedKeySet,
+    metrics: &Metrics,
+) -> eyre::Result<String>
+where
+    R: Stream<Item = io::Result<BytesMut>> + Unpin,
+    W: Sink<Bytes, Error = io::Error> + Unpin,
+{
+    let challenge = challenge().wrap_err("failed to generate challenge")?;
+    write_stream
+        .send(protocol::encode_hello(&AuthChallenge {
+            challenge: Bytes::copy_from_slice(&challenge),
+        })?)
+        .await
+        .wrap_err("failed to send challenge")?;
+
+    for _ in 0..MAX_ATTEMPTS {
+        let frame = read_stream
+            .try_next()
+            .await
+            .wrap_err("failed to receive authentication")?
+            .ok_or_else(|| eyre!("client gave up authenticating"))?;
+        let AuthResponse {
+            public_key,
+            signature,
+        } = protocol::decode_hello(&frame)
+            .ok_or_else(|| eyre!("client did not authenticate"))?
+            .wrap_err("invalid authentication")?;
+        let res = keys
+            .verify(&challenge, &public_key, &signature)
+            .map(describe);
+        let reply: AuthReply = res.as_ref().map(|_| ()).map_err(ToString::to_string);
+        write_stream
+            .send(protocol::encode_hello(&reply)?)
+            .await
+            .wrap_err("failed to send authentication result")?;
+        match res {
+            Ok(key) => return Ok(key),
+            Err(e) => {
+                metrics.auth_failures.inc();
+                tracing::info!("rejected key: {e}");
+            }
+        }
+    }
+    bail!("too many authentication attempts")
+}
+
+/// Signs the server's challenge with the keys of the ssh-agent until the server accepts one.
+///
+/// Only the key named by `identity` is tried if it is set, see [`is_identity`].
+pub async fn sign_challenge<R, W>(
+    read_stream: &mut R,
+    write_stream: &mut W,
+    identity: Option<&str>,
+) -> eyre::Result<()>
+where
+    R: Stream<Item = io::Result<BytesMut>> + Unpin,
+    W: Sink<Bytes, Error = io::Error> + Unpin,
+{
+    let frame = read_stream
+        .try_next()
+        .await
+        .wrap_err("failed to receive challenge")?
+        .ok_or_else(|| eyre!("server closed the connection before sending a challenge"))?;
+    let AuthChallenge { challenge } = protocol::decode_hello(&frame)
+        .ok_or_else(|| eyre!("server did not send a challenge"))?
+        .wrap_err("invalid challenge")?;
+
+    let mut agent = Agent::connect_env()
+        .await
+        .wrap_err("failed to connect to ssh-agent")
+        .suggestion(
+            "the server requires authentication: start ssh-agent and add a key with ssh-add",
+        )?;
+    let keys = agent
+        .identities()
+        .await
+        .wrap_err("failed to list the keys of ssh-agent")?
+        .into_iter()
+        .filter(|key| identity.is_none_or(|identity| is_identity(key, identity)))
+        .collect::<Vec<_>>();
+    if keys.is_empty() {
+        let report = eyre!("ssh-agent has no key to authenticate with");
+        return Err(match identity {
+            Some(identity) => report.suggestion(format!("add the key {identity} with ssh-add")),
+            None => report.suggestion("add a key with ssh-add"),
+        });
+    }
+
+    let data = signed_data(&challenge);
+    for key in keys.iter().take(MAX_ATTEMPTS) {
+        let key_name = describe(key);
+        let signature = match agent.sign(key, &data).await {
+            Ok(signature) => signature,
+            Err(e) => {
+                tracing::warn!("ssh-agent failed to sign with key {key_name}: {e}");
+                continue;
+            }
+        };
+        let response = AuthResponse {
+            public_key: key.to_bytes()?.into(),
+            signature: Vec::<u8>::try_from(signature)?.into(),
+        };
+        write_stream
+            .send(protocol::encode_hello(&response)?)
+            .await
+            .wrap_err("failed to send authentication")?;
+        let frame = read_stream
+            .try_next()
+            .await
+            .wrap_err("failed to receive authentication result")?
+            .ok_or_else(|| eyre!("server closed the connection during authentication"))?;
+        let reply = protocol::decode_hello::<AuthReply>(&frame)
+            .ok_or_else(|| eyre!("server did not reply to the authentication"))?
+            .wrap_err("invalid authentication result")?;
+        match reply {
+            Ok(()) => {
+                tracing::debug!("authenticated with key {key_name}");
+                return Ok(());
+            }
+            Err(reason) => tracing::info!("server rejected key {key_name}: {reason}"),
+        }
+    }
+    bail!("server accepted none of the keys of ssh-agent")
+}
@@ -0,0 +1,308 @@
+use std::{
+    env,
+    fs::File,
+    io::BufReader,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+
+use clap::Parser as _;
+use color_eyre::eyre::{self, bail, eyre, WrapErr as _};
+use git_remote_utils::{
+    admin::{self, Request, Response, SessionInfo},
+    audit,
+    config::{self, OutputFormat},
+    credential::Description,
+    soak::{self, Report, SoakConfig},
+    version,
+};
+
+/// Git remote utils credential helper server administration
+#[derive(Debug, clap::Parser)]
+#[clap(author, version, long_version = version::LONG_VERSION, about)]
+struct Args {
+    /// Path of the server's admin socket
+    #[clap(
+        short,
+        long,
+        value_name = "PATH",
+        env = "GRU_CREDENTIAL_HELPER_ADMIN_SOCKET"
+    )]
+    socket: Option<PathBuf>,
+    /// Output format
+    #[clap(
+        short,
+        long,
+        value_name = "FORMAT",
+        value_enum,
+        default_value_t,
+        global = true
+    )]
+    output: OutputFormat,
+    /// Command to execute
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Show the sessions currently running as a table
+    Status,
+    /// List the sessions currently running
+    ListSessions,
+    /// Terminate a session, killing its process
+    KillSession {
+        /// Session id, as shown by `status`
+        id: u64,
+    },
+    /// Show the server's metrics
+    Stats,
+    /// Check that the records of an audit log (`--audit-log` of the server) are intact, without
+    /// connecting to the server
+    VerifyAuditLog {
+        /// Path of the audit log
+        path: PathBuf,
+    },
+    /// Run sessions from many clients at once against a server for a while, and report the error
+    /// rate, the latencies and the server's resource usage
+    Soak {
+        /// Server's address, as given to the client's `--connect`
+        #[clap(short, long = "connect", value_name = "ADDRESS")]
+        connect_addr: String,
+        /// URL of the remote the credentials are requested for
+        #[clap(
+            long,
+            value_name = "URL",
+            default_value = "https://example.com/soak.git"
+        )]
+        url: String,
+        /// Credential operation run by each session
+        #[clap(long, value_name = "OPERATION", default_value = "get", value_parser = ["get", "store", "erase"])]
+        operation: String,
+        /// Number of sessions kept running at once
+        #[clap(long, value_name = "COUNT", default_value = "8")]
+        clients: NonZeroUsize,
+        /// How long to start new sessions for
+        #[clap(
+            long,
+            value_name = "DURATION",
+            default_value = "30s",
+            value_parser = |s: &str| config::parse_duration("--duration", s.into())
+        )]
+        duration: Duration,
+        /// Time after which a session's client is killed and the session counted as failed
+        #[clap(
+            long,
+            value_name = "DURATION",
+            default_value = "30s",
+            value_parser = |s: &str| config::parse_duration("--session-timeout", s.into())
+        )]
+        session_timeout: Duration,
+        /// Process id of the server, to sample its memory and open files from /proc
+        #[clap(long, value_name = "PID")]
+        server_pid: Option<u32>,
+        /// Path of the client [default: gru-credential-helper-client next to this program]
+        #[clap(long, value_name = "PATH")]
+        client: Option<PathBuf>,
+    },
+}
+
+#[tokio::main]
+async fn main() -> eyre::Result<ExitCode> {
+    color_eyre::install()?;
+
+    let Args {
+        socket,
+        output,
+        command,
+    } = Args::parse();
+    let request = match command {
+        Command::Status | Command::ListSessions => Request::ListSessions,
+        Command::KillSession { id } => Request::KillSession { id },
+        Command::Stats => Request::Stats,
+        Command::VerifyAuditLog { path } => return verify_audit_log(&path, output),
+        Command::Soak {
+            connect_addr,
+            url,
+            operation,
+            clients,
+            duration,
+            session_timeout,
+            server_pid,
+            client,
+        } => {
+            let client = match client {
+                Some(client) => client,
+                None => env::current_exe()
+                    .wrap_err("failed to locate the client")?
+                    .with_file_name("gru-credential-helper-client"),
+            };
+            let input = Description::from_url(&url)
+                .ok_or_else(|| eyre!("invalid URL: {url}"))?
+                .to_input();
+            let config = SoakConfig {
+                client,
+                connect_addr,
+                operation,
+                input,
+                clients: clients.get(),
+                duration,
+                session_timeout,
+                server_pid,
+            };
+            return soak(&config, output).await;
+        }
+    };
+    let socket = socket.ok_or_else(|| {
+        eyre!("admin socket is not specified (use --socket or GRU_CREDENTIAL_HELPER_ADMIN_SOCKET)")
+    })?;
+    let response = admin::request(&socket, &request)
+        .await
+        .wrap_err_with(|| format!("failed to send request to {}", socket.display()))?;
+
+    match (command, output, response) {
+        (_, _, Response::Error { message }) => {
+            eprintln!("Error: {message}");
+            return Ok(ExitCode::FAILURE);
+        }
+        (Command::Status, OutputFormat::Json, Response::Sessions { sessions }) => {
+            println!("{}", serde_json::to_string_pretty(&sessions)?);
+        }
+        (Command::Status, _, response @ (Response::Killed { .. } | Response::Stats { .. })) => {
+            bail!("unexpected response: {response:?}")
+        }
+        (_, OutputFormat::Json, response) => {
+            println!("{}", serde_json::to_string_pretty(&response)?);
+        }
+        (_, OutputFormat::Text, Response::Sessions { sessions }) => print_table(&sessions),
+        (_, OutputFormat::Text, Response::Killed { id }) => println!("killed session {id}"),
+        (_, OutputFormat::Text, Response::Stats { counters, gauges }) => {
+            let values = counters
+                .into_iter()
+                .map(|(name, value)| (name, value.to_string()))
+                .chain(
+                    gauges
+                        .into_iter()
+                        .map(|(name, value)| (name, value.to_string())),
+                )
+                .collect::<Vec<_>>();
+            let width = values.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+            for (name, value) in values {
+                println!("{name:<width$}  {value}");
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn soak(config: &SoakConfig, output: OutputFormat) -> eyre::Result<ExitCode> {
+    let report = soak::run(config).await.wrap_err("soak test failed")?;
+    match output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        OutputFormat::Text => {
+            let Report {
+                sessions,
+                failures,
+                error_rate,
+                sessions_per_second,
+                latency_ms,
+                failures_by_exit,
+                server,
+            } = &report;
+            println!("sessions  {sessions} ({sessions_per_second:.1}/s)");
+            println!("failures  {failures} ({:.2}%)", error_rate * 100.0);
+            for (exit, count) in failures_by_exit {
+                println!("          {count} {exit}");
+            }
+            println!(
+                "latency   p50 {:.1} ms, p90 {:.1} ms, p99 {:.1} ms, max {:.1} ms",
+                latency_ms.p50, latency_ms.p90, latency_ms.p99, latency_ms.max
+            );
+            if let Some(server) = server {
+                println!(
+                    "server    peak {} KiB, {} fds; final {} KiB, {} fds",
+                    server.peak_rss_bytes / 1024,
+                    server.peak_fds,
+                    server.final_rss_bytes / 1024,
+                    server.final_fds
+                );
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn verify_audit_log(path: &Path, output: OutputFormat) -> eyre::Result<ExitCode> {
+    let file = File::open(path)
+        .wrap_err_with(|| format!("failed to open audit log: {}", path.display()))?;
+    match audit::verify(BufReader::new(file)) {
+        Ok(verified) => {
+            match output {
+                OutputFormat::Text => {
+                    println!(
+                        "{} records, last hash {}",
+                        verified.records, verified.last_hash
+                    );
+                }
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&verified)?),
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Err(e) => {
+            eprintln!("Error: {}: {e}", path.display());
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+fn print_table(sessions: &[SessionInfo]) {
+    let rows = sessions
+        .iter()
+        .map(|session| {
+            let total = session.bytes_received + session.bytes_sent;
+            let rate = if session.duration > 0.0 {
+                total as f64 / session.duration
+            } else {
+                0.0
+            };
+            [
+                session.id.to_string(),
+                session.peer.clone(),
+                session.url.clone().unwrap_or_else(|| "-".into()),
+                session
+                    .command
+                    .map_or("-".into(), |command| format!("{command:?}").to_lowercase()),
+                session.pid.map_or("-".into(), |pid| pid.to_string()),
+                session.bytes_received.to_string(),
+                session.bytes_sent.to_string(),
+                format!("{rate:.0}"),
+                format!("{:.1}", session.duration),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let header = [
+        "ID", "PEER", "URL", "COMMAND", "PID", "RECEIVED", "SENT", "BYTES/S", "SECONDS",
+    ];
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let print_row = |cells: &[&str]| {
+        let line = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    };
+    print_row(&header);
+    for row in &rows {
+        print_row(&row.each_ref().map(String::as_str));
+    }
+}
@@ -1,101 +1,341 @@
-use std::{fmt::Debug, io, sync::Arc, thread};
+use std::{
+    fmt::Debug,
+    fs::File,
+    io::{self, BufWriter, Read as _, Write as _},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
 
 use bytes::BytesMut;
 use clap::Parser as _;
-use color_eyre::eyre::{self, WrapErr as _};
+use color_eyre::{
+    eyre::{self, eyre, WrapErr as _},
+    Section as _,
+};
 use futures::{future, stream, SinkExt as _, StreamExt as _, TryStreamExt};
+#[cfg(feature = "ssh-agent-auth")]
+use git_remote_utils::auth;
 use git_remote_utils::{
     self as gru,
+    config::{self, ClientConfig, ClientOptions, OutputFormat},
+    credential::Description,
+    dial,
+    exit::{self, Failure},
+    hint, log,
     protocol::{
-        self, ClientMessage, Command, OutputRequest, OutputResponse, ServerMessage, SpawnMessage,
+        self, ClientHello, ClientMessage, Command, Exit, HelloReply, OutputRequest, OutputResponse,
+        Priority, ServerHello, ServerMessage, SessionHello, SpawnMessage, MAX_FRAME_LENGTH,
+        MAX_HANDSHAKE_FRAME_LENGTH, PRIORITY_FEATURE,
     },
-    socket::SocketStream,
+    sanitize::{self, Sanitizer},
+    socket::{OwnedReadHalf, OwnedWriteHalf, ToSocketAddrs as _},
+    stdio::AsyncStdio,
+    trace::ChromeTrace,
+    transport::ConnectError,
+    version,
 };
-use tokio::sync::mpsc;
+use tokio::{process::Child, sync::mpsc};
 use tokio_stream::wrappers::ReceiverStream;
-use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
+use tokio_util::{
+    codec::{FramedRead, FramedWrite, LengthDelimitedCodec},
+    sync::CancellationToken,
+};
 use tracing::Instrument;
+use ulid::Ulid;
 
 /// Git remote utils credential helper client
 #[derive(Debug, clap::Parser)]
-#[clap(author, version, about)]
+#[clap(author, version, long_version = version::LONG_VERSION, about)]
 struct Args {
     /// Server's internet socket address (address:port) or Unix socket address (path)
+    ///
+    /// Defaults to `gru.connect` git config matching the credential's URL.
+    #[clap(short, long = "connect", value_name = "ADDRESS")]
+    connect_addr: Option<String>,
+    /// Command used to reach the server instead of connecting directly, with its stdio used as the
+    /// connection (`%h`, `%p` and `%a` are replaced with host, port and address)
+    ///
+    /// Defaults to `gru.dialCommand` git config matching the credential's URL.
+    #[clap(long, value_name = "COMMAND")]
+    dial_command: Option<String>,
+    /// Timeout for connecting to the server (e.g. `10s`, `500ms`)
+    ///
+    /// With a dial command, only spawning the command is covered.
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = |s: &str| config::parse_duration("--timeout", s.into())
+    )]
+    timeout: Option<Duration>,
+    /// Size of the buffer used to read stdin
     #[clap(
-        short,
-        long = "connect",
-        value_name = "ADDRESS",
-        env = "GRU_CREDENTIAL_HELPER_CONNECT_ADDR"
+        long,
+        value_name = "BYTES",
+        value_parser = |s: &str| config::parse_buffer_size("--buffer-size", s.into())
     )]
-    connect_addr: String,
+    buffer_size: Option<usize>,
+    /// Scheduling class of the session on the server
+    ///
+    /// Defaults to `interactive` when stderr is a terminal and `batch` otherwise.
+    #[clap(long, value_name = "PRIORITY", value_enum)]
+    priority: Option<Priority>,
+    /// ssh-agent key to sign the server's challenge with, by SHA-256 fingerprint (`SHA256:...`)
+    /// or comment, when the server requires authentication
+    ///
+    /// Defaults to `gru.identity` git config matching the credential's URL, or to trying each key
+    /// of the agent in turn.
+    #[clap(long, value_name = "KEY")]
+    identity: Option<String>,
+    /// Increase log verbosity (`-v` warnings, `-vv` info, `-vvv` debug, `-vvvv` trace)
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Print what would be done to stderr and exit without connecting to the server
+    #[clap(long)]
+    dry_run: bool,
+    /// Format of the `--dry-run` output
+    #[clap(long, value_name = "FORMAT", value_enum, default_value_t)]
+    output: OutputFormat,
+    /// Write the timings of the phases of the operation (resolve, connect, handshake,
+    /// authentication and the transfer of each stdio stream) to PATH, as Chrome trace events
+    /// viewable in chrome://tracing or Perfetto
+    #[clap(long, value_name = "PATH")]
+    timing_out: Option<PathBuf>,
     /// Command to execute
     #[clap(subcommand)]
     command: Command,
 }
 
 #[tokio::main]
-async fn main() -> eyre::Result<()> {
-    color_eyre::install()?;
-    tracing_subscriber::fmt::init();
+async fn main() -> ExitCode {
+    // generated upfront, so that any error can be quoted with it
+    let session_id = Ulid::new();
+    match run(session_id).await {
+        Ok(code) => code,
+        Err(report) => {
+            let report = report.note(format!("session id: {session_id}"));
+            // stderr may be closed as well when git is interrupted
+            let _ = writeln!(io::stderr(), "Error: {report:?}");
+            exit::report_code(&report)
+        }
+    }
+}
+
+async fn run(session_id: Ulid) -> eyre::Result<ExitCode> {
+    exit::install_hooks(env!("CARGO_BIN_NAME"))?;
 
+    let args = match Args::try_parse() {
+        Ok(args) => args,
+        // `--help` and `--version` are reported as errors too
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            let _ = e.print();
+            return Ok(Failure::Usage.into());
+        }
+    };
     let Args {
         connect_addr,
+        dial_command,
+        timeout,
+        buffer_size,
+        priority,
+        identity,
+        verbose,
+        dry_run,
+        output,
+        timing_out,
         command,
-    } = Args::parse();
+    } = args;
+    let cli_options = ClientOptions {
+        connect_addr,
+        dial_command,
+        timeout,
+        buffer_size,
+        priority,
+        identity,
+        log: (verbose > 0).then(|| log::verbosity_filter(verbose).to_owned()),
+    };
+    let env_options = ClientOptions::from_env().wrap_err(Failure::Config)?;
 
-    let stream = SocketStream::connect(&connect_addr)
-        .await
-        .wrap_err_with(|| format!("failed to connect socket: {connect_addr}"))?;
-    let (read_stream, write_stream) = stream.into_split();
+    let mut options = cli_options.or(env_options);
+    let trace = timing_out.is_some().then(ChromeTrace::default);
+    log::init_with_trace(
+        options.log.as_deref(),
+        log::verbosity_filter(0),
+        trace.as_ref(),
+    )
+    .wrap_err("invalid log filter")
+    .wrap_err(Failure::Config)?;
+
+    // git writes the whole credential description and closes stdin before reading the output,
+    // so it can be read upfront to find out which remote is being accessed.
+    let mut input = Vec::new();
+    io::stdin()
+        .read_to_end(&mut input)
+        .wrap_err("failed to read stdin")
+        .wrap_err(Failure::Transfer)?;
+
+    let url = Description::parse(&input).url();
+    if let Some(url) = &url {
+        let git_options = ClientOptions::from_git_config(url)
+            .await
+            .wrap_err(Failure::Config)?;
+        options = options.or(git_options);
+    }
+    let config = ClientConfig::try_from(options).wrap_err(Failure::Config)?;
+    tracing::debug!("resolved config: {config:?}");
+
+    if dry_run {
+        Plan::new(&config, command)
+            .await
+            .wrap_err(Failure::Config)?
+            .print(output)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let res = session(config, command, url, input, session_id)
+        .instrument(tracing::info_span!("session", id = %session_id))
+        .await;
+    if let (Some(path), Some(trace)) = (&timing_out, &trace) {
+        // the credential operation is done, so a failure here doesn't change the exit status
+        if let Err(report) = write_trace(path, trace) {
+            let _ = writeln!(io::stderr(), "Warning: {report:?}");
+        }
+    }
+    res
+}
+
+fn write_trace(path: &Path, trace: &ChromeTrace) -> eyre::Result<()> {
+    let file = File::create(path)
+        .wrap_err_with(|| format!("failed to create timing file: {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    trace
+        .write(&mut writer)
+        .and_then(|()| writer.flush())
+        .wrap_err_with(|| format!("failed to write timing file: {}", path.display()))
+}
 
-    let read_stream = FramedRead::new(read_stream, LengthDelimitedCodec::new());
-    let mut write_stream = FramedWrite::new(write_stream, LengthDelimitedCodec::new());
+/// Runs `command` on the server, forwarding the credential description in `input` and the output.
+async fn session(
+    config: ClientConfig,
+    command: Command,
+    url: Option<String>,
+    input: Vec<u8>,
+    session_id: Ulid,
+) -> eyre::Result<ExitCode> {
+    let (mut dial_child, mut read_stream, mut write_stream) = open(&config)
+        .instrument(tracing::info_span!("connect"))
+        .await
+        .wrap_err(Failure::Connect)?;
+    let hello = ClientHello::new(command, url);
+    let server_hello = handshake(&mut read_stream, &mut write_stream, &hello)
+        .await
+        .wrap_err(Failure::Protocol)?;
+    if let Some(hello) = &server_hello {
+        if hello.has_feature(protocol::SESSION_ID_FEATURE) {
+            write_stream
+                .send(protocol::encode_hello(&SessionHello { session_id })?)
+                .await
+                .wrap_err("failed to send session id")
+                .wrap_err(Failure::Protocol)?;
+        }
+    }
+    let send_priority = matches!(&server_hello, Some(hello) if hello.has_feature(PRIORITY_FEATURE));
+    match server_hello {
+        #[cfg(feature = "ssh-agent-auth")]
+        Some(hello) if hello.has_feature(protocol::AUTH_FEATURE) => {
+            auth::sign_challenge(
+                &mut read_stream,
+                &mut write_stream,
+                config.identity.as_deref(),
+            )
+            .instrument(tracing::info_span!("auth"))
+            .await
+            .wrap_err(Failure::Auth)?;
+        }
+        Some(_) => {}
+        None => {
+            // servers without the handshake close the connection when they receive it
+            tracing::warn!("server does not support the handshake, reconnecting without it");
+            (dial_child, read_stream, write_stream) = open(&config)
+                .instrument(tracing::info_span!("connect"))
+                .await
+                .wrap_err(Failure::Connect)?;
+        }
+    }
 
     protocol::new_sender(&mut write_stream)
-        .send(SpawnMessage { command })
+        .send(SpawnMessage {
+            command,
+            priority: send_priority.then_some(config.priority),
+        })
         .await
-        .wrap_err("failed to send spawn request")?;
+        .wrap_err("failed to send spawn request")
+        .wrap_err(Failure::Protocol)?;
+    read_stream
+        .decoder_mut()
+        .set_max_frame_length(MAX_FRAME_LENGTH);
 
     let receiver = protocol::new_receiver::<_, ServerMessage>(read_stream);
     let mut sender = protocol::new_sender::<_, ClientMessage>(write_stream);
 
     let (stdin_bytes_tx, stdin_bytes_rx) = mpsc::channel(1);
     let (stdin_res_tx, stdin_res_rx) = mpsc::channel(1);
+    let buffer_size = config.buffer_size;
+    // spans of threads are created beforehand, so that they are children of the session's span
+    let stdin_span = tracing::info_span!("stdin");
     let _stdin_thread = thread::Builder::new()
         .name("stdin".into())
-        .spawn(|| {
-            let _span = tracing::info_span!("stdin").entered();
-            gru::thread::input(io::stdin(), stdin_bytes_tx, stdin_res_rx)
+        .spawn(move || {
+            let _span = stdin_span.entered();
+            gru::thread::input(
+                io::Cursor::new(input),
+                buffer_size,
+                stdin_bytes_tx,
+                stdin_res_rx,
+            )
         })
         .wrap_err("failed to spawn thread")?;
 
     let (stdout_bytes_tx, stdout_bytes_rx) = mpsc::channel(1);
     let (stdout_res_tx, stdout_res_rx) = mpsc::channel(1);
-    let stdout_thread = thread::Builder::new()
-        .name("stdout".into())
-        .spawn(|| {
-            let _span = tracing::info_span!("stdout").entered();
-            gru::thread::output(io::stdout(), stdout_res_tx, stdout_bytes_rx)
-        })
-        .wrap_err("failed to spawn thread")?;
+    // stdout is usually a pipe from git, which can be polled without a dedicated thread
+    let stdout_span = tracing::info_span!("stdout");
+    let stdout_pump = match AsyncStdio::stdout().wrap_err("failed to set up stdout")? {
+        Some(stdout) => Pump::Task(tokio::spawn(
+            gru::task::output(stdout, stdout_res_tx, stdout_bytes_rx).instrument(stdout_span),
+        )),
+        None => Pump::Thread(
+            thread::Builder::new()
+                .name("stdout".into())
+                .spawn(move || {
+                    let _span = stdout_span.entered();
+                    gru::thread::output(io::stdout(), stdout_res_tx, stdout_bytes_rx)
+                })
+                .wrap_err("failed to spawn thread")?,
+        ),
+    };
 
     let (stderr_bytes_tx, stderr_bytes_rx) = mpsc::channel(1);
     let (stderr_res_tx, stderr_res_rx) = mpsc::channel(1);
+    let stderr_span = tracing::info_span!("stderr");
     let stderr_thread = thread::Builder::new()
         .name("stderr".into())
         .spawn(|| {
-            let _span = tracing::info_span!("stderr").entered();
+            let _span = stderr_span.entered();
             gru::thread::output(io::stderr(), stderr_res_tx, stderr_bytes_rx)
         })
         .wrap_err("failed to spawn thread")?;
 
-    tokio::spawn(receive(
-        receiver,
-        stdin_res_tx,
-        stdout_bytes_tx,
-        stderr_bytes_tx,
-    ));
-    tokio::spawn(
+    let receive_task = tokio::spawn(
+        receive(receiver, stdin_res_tx, stdout_bytes_tx, stderr_bytes_tx).in_current_span(),
+    );
+    // git has gone away if it closed stdout, so nobody is waiting for the rest of the session
+    let stdout_closed = CancellationToken::new();
+    tokio::spawn({
+        let stdout_closed = stdout_closed.clone();
         async move {
             let stdin = ReceiverStream::new(stdin_bytes_rx)
                 .map(OutputRequest::Output)
@@ -110,20 +350,196 @@ async fn main() -> eyre::Result<()> {
             let mut stream = stream::select(stdin, stream::select(stdout, stderr));
             while let Some(msg) = stream.next().await {
                 tracing::trace!("sending message: {msg:?}");
+                // the server stops sending output once it is told about the error
+                let closed = matches!(msg, ClientMessage::Stdout(OutputResponse(Err(_))));
                 match sender.send(msg).await {
                     Ok(()) => tracing::trace!("message sent"),
                     Err(e) => tracing::error!("failed to send message: {e:?}"),
                 }
+                if closed {
+                    stdout_closed.cancel();
+                }
             }
         }
-        .instrument(tracing::info_span!("send")),
-    );
+        .instrument(tracing::info_span!("send"))
+    });
+
+    let finished = async {
+        //let _stdin_res = stdin_thread.join();
+        let _stdout_res = stdout_pump.join().await;
+        let _stderr_res = stderr_thread.join();
+        receive_task.await.wrap_err("failed to join receive task")
+    };
+    let exit = tokio::select! {
+        res = finished => res?,
+        () = stdout_closed.cancelled() => {
+            // the connection is closed on return, which makes the server kill the command
+            if let Some(child) = &mut dial_child {
+                let _ = child.kill().await;
+            }
+            return Err(eyre!("stdout was closed before the end of the session"))
+                .wrap_err(Failure::Transfer);
+        }
+    };
+    let exit = exit
+        .wrap_err(Failure::Protocol)?
+        .ok_or_else(|| eyre!("server did not report the exit status"))
+        .wrap_err(Failure::Protocol)?;
+    if let Exit::OtherError(e) = &exit {
+        let _ = writeln!(
+            io::stderr(),
+            "Error: {}: {}",
+            Failure::Remote,
+            sanitize::text(e)
+        );
+    }
+    Ok(exit::remote_code(&exit))
+}
+
+/// Task or thread copying data to one of the client's stdio.
+enum Pump {
+    Task(tokio::task::JoinHandle<eyre::Result<()>>),
+    Thread(thread::JoinHandle<eyre::Result<()>>),
+}
 
-    //let _stdin_res = stdin_thread.join();
-    let _stdout_res = stdout_thread.join();
-    let _stderr_res = stderr_thread.join();
+impl Pump {
+    async fn join(self) -> eyre::Result<()> {
+        match self {
+            Self::Task(task) => task.await.wrap_err("failed to join task")?,
+            Self::Thread(thread) => thread.join().map_err(|_| eyre!("thread panicked"))?,
+        }
+    }
+}
 
-    Ok(())
+/// What the client would do, as shown by `--dry-run`.
+#[derive(Debug, serde::Serialize)]
+struct Plan {
+    /// Dial command, with the tokens expanded
+    dial_command: Option<String>,
+    /// Resolved addresses, when connecting directly
+    connect: Vec<String>,
+    timeout_ms: Option<u64>,
+    buffer_size: usize,
+    priority: Priority,
+    identity: Option<String>,
+    server_command: String,
+}
+
+impl Plan {
+    async fn new(config: &ClientConfig, command: Command) -> eyre::Result<Self> {
+        let connect_addr = &config.connect_addr;
+        let (dial_command, connect) = match &config.dial_command {
+            Some(dial_command) => {
+                let dial_command = dial::expand(dial_command, connect_addr)
+                    .wrap_err_with(|| format!("invalid dial command: {dial_command}"))?;
+                (Some(dial_command), vec![])
+            }
+            None => {
+                let addrs = connect_addr
+                    .to_socket_addrs()
+                    .await
+                    .wrap_err_with(|| format!("failed to resolve address: {connect_addr}"))?;
+                (None, addrs.map(|addr| addr.to_string()).collect())
+            }
+        };
+        Ok(Self {
+            dial_command,
+            connect,
+            timeout_ms: config
+                .timeout
+                .map(|timeout| timeout.as_millis().try_into().unwrap_or(u64::MAX)),
+            buffer_size: config.buffer_size,
+            priority: config.priority,
+            identity: config.identity.clone(),
+            server_command: format!("git {}", command.git_args().join(" ")),
+        })
+    }
+
+    fn print(&self, format: OutputFormat) -> eyre::Result<()> {
+        // stdout is read by git, so the plan goes to stderr
+        if format == OutputFormat::Json {
+            eprintln!("{}", serde_json::to_string_pretty(self)?);
+            return Ok(());
+        }
+        if let Some(dial_command) = &self.dial_command {
+            eprintln!("dial command: sh -c {dial_command:?}");
+        }
+        for addr in &self.connect {
+            eprintln!("connect: {addr}");
+        }
+        if let Some(timeout) = self.timeout_ms {
+            eprintln!("timeout: {:?}", Duration::from_millis(timeout));
+        }
+        eprintln!("buffer size: {}", self.buffer_size);
+        eprintln!("priority: {:?}", self.priority);
+        if let Some(identity) = &self.identity {
+            eprintln!("identity: {identity}");
+        }
+        eprintln!("server command: {}", self.server_command);
+        Ok(())
+    }
+}
+
+type Connection = (
+    Option<Child>,
+    FramedRead<OwnedReadHalf, LengthDelimitedCodec>,
+    FramedWrite<OwnedWriteHalf, LengthDelimitedCodec>,
+);
+
+/// Connects to the server, within the configured timeout.
+async fn open(config: &ClientConfig) -> eyre::Result<Connection> {
+    let connect_addr = &config.connect_addr;
+    let connection = config
+        .transport()
+        .connect(connect_addr)
+        .await
+        .map_err(|e| {
+            let hint = match &e {
+                ConnectError::Timeout { .. } => {
+                    Some("check the network or increase --timeout".into())
+                }
+                ConnectError::Connect { source, .. } => hint::connect(source, connect_addr),
+                ConnectError::Dial { .. } => None,
+            };
+            let report = eyre::Report::new(e);
+            match hint {
+                Some(hint) => report.suggestion(hint),
+                None => report,
+            }
+        })?;
+    Ok((
+        connection.dial_child,
+        FramedRead::new(connection.read, protocol::codec(MAX_HANDSHAKE_FRAME_LENGTH)),
+        FramedWrite::new(connection.write, protocol::codec(MAX_FRAME_LENGTH)),
+    ))
+}
+
+/// Negotiates the connection parameters, returning `None` if the server closed the connection
+/// without replying.
+#[tracing::instrument(level = "info", skip_all)]
+async fn handshake(
+    read_stream: &mut FramedRead<OwnedReadHalf, LengthDelimitedCodec>,
+    write_stream: &mut FramedWrite<OwnedWriteHalf, LengthDelimitedCodec>,
+    hello: &ClientHello,
+) -> eyre::Result<Option<ServerHello>> {
+    write_stream
+        .send(protocol::encode_hello(hello)?)
+        .await
+        .wrap_err("failed to send handshake")?;
+    let frame = match read_stream.try_next().await {
+        Ok(Some(frame)) => frame,
+        Ok(None) => return Ok(None),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionReset => return Ok(None),
+        Err(e) => return Err(e).wrap_err("failed to receive handshake"),
+    };
+    let reply = protocol::decode_hello::<HelloReply>(&frame)
+        .ok_or_else(|| eyre!("server did not reply to the handshake"))?
+        .wrap_err("invalid handshake")?;
+    let hello = reply
+        .map_err(|reason| eyre!("server rejected the handshake: {}", sanitize::text(&reason)))?;
+    tracing::debug!("received handshake: {hello:?}");
+    hello.validate().map_err(|reason| eyre!(reason))?;
+    Ok(Some(hello))
 }
 
 #[tracing::instrument(level = "debug", err, ret, skip_all)]
@@ -132,9 +548,12 @@ async fn receive(
     stdin_tx: mpsc::Sender<Result<(), String>>,
     stdout_tx: mpsc::Sender<Arc<BytesMut>>,
     stderr_tx: mpsc::Sender<Arc<BytesMut>>,
-) -> eyre::Result<()> {
+) -> eyre::Result<Option<Exit>> {
+    let mut exit = None;
     let mut stdout_tx = Some(stdout_tx);
     let mut stderr_tx = Some(stderr_tx);
+    // stdout is read by git, stderr is shown to the user
+    let mut stderr_sanitizer = Sanitizer::default();
     while let Some(msg) = receiver
         .try_next()
         .await
@@ -142,9 +561,9 @@ async fn receive(
     {
         tracing::trace!("received message: {:?}", msg);
         match msg {
-            ServerMessage::Exit(_code) => {
-                // tracing::info!("server exited with code: {}", code);
-                // return Ok(());
+            ServerMessage::Exit(msg) => {
+                tracing::debug!("server process exited: {msg:?}");
+                exit = Some(msg);
             }
             ServerMessage::Stdin(msg) => match msg {
                 OutputResponse(msg) => {
@@ -158,7 +577,7 @@ async fn receive(
                 OutputRequest::Output(msg) => {
                     stdout_tx
                         .as_mut()
-                        .unwrap()
+                        .ok_or_else(|| eyre!("server sent stdout after closing it"))?
                         .send(msg)
                         .await
                         .wrap_err("failed to send message")?;
@@ -169,9 +588,13 @@ async fn receive(
             },
             ServerMessage::Stderr(msg) => match msg {
                 OutputRequest::Output(msg) => {
+                    let mut filtered = Vec::with_capacity(msg.len());
+                    stderr_sanitizer.filter(&msg, &mut filtered);
+                    // sent even if empty, as the server waits for each chunk to be acknowledged
+                    let msg = Arc::new(BytesMut::from(&filtered[..]));
                     stderr_tx
                         .as_mut()
-                        .unwrap()
+                        .ok_or_else(|| eyre!("server sent stderr after closing it"))?
                         .send(msg)
                         .await
                         .wrap_err("failed to send message")?;
@@ -183,5 +606,5 @@ async fn receive(
         }
     }
 
-    Ok(())
+    Ok(exit)
 }
@@ -1,54 +1,720 @@
-use std::{io, os::unix::prelude::ExitStatusExt, process::Stdio, sync::Arc};
+use std::{
+    future::Future,
+    io,
+    num::NonZeroUsize,
+    os::unix::prelude::{AsRawFd as _, ExitStatusExt, IntoRawFd as _},
+    path::PathBuf,
+    pin::pin,
+    process::Stdio,
+    sync::Arc,
+    time::Duration,
+};
 
 use bytes::BytesMut;
 use clap::Parser as _;
-use color_eyre::eyre::{self, eyre, WrapErr as _};
+use color_eyre::{
+    eyre::{self, bail, eyre, WrapErr as _},
+    Section as _,
+};
 use futures::{channel::oneshot, future, stream, SinkExt, StreamExt as _, TryStreamExt};
+#[cfg(feature = "ssh-agent-auth")]
+use git_remote_utils::auth::{self, AuthorizedKeySet};
 use git_remote_utils::{
     self as gru,
+    admin::{self, SessionHandle, Sessions, Traffic},
+    audit::{AuditLog, AuditWriter},
+    budget::Budget,
+    config::{self, DEFAULT_BUFFER_SIZE},
+    daemon::{self, Daemon},
+    event::{EventQueue, Target},
+    fault::{Faults, OutputFault},
+    handover::{self, Handover},
+    health::{self, Health},
+    hint,
+    isolation::{ChildLimits, IoPriority},
+    log,
+    metrics::{self, Leaks, Metrics, PushGateway, Sink, Statsd, TaskGuard},
+    pidfile::PidFile,
+    privilege::{self, Account},
     protocol::{
-        self, ClientMessage, Command, Exit, OutputRequest, OutputResponse, ServerMessage,
-        SpawnMessage,
+        self, ClientHello, ClientMessage, Exit, OutputRequest, OutputResponse, ServerMessage,
+        SessionHello, SpawnMessage, AUTH_FEATURE, FEATURES, MAX_FRAME_LENGTH,
+        MAX_HANDSHAKE_FRAME_LENGTH, PRIORITY_FEATURE, SESSION_ID_FEATURE,
     },
-    socket::{SocketListener, SocketStream},
+    socket::{
+        OwnedReadHalf, OwnedWriteHalf, SocketAddr, SocketListener, SocketLock, SocketStream,
+        ToSocketAddrs as _,
+    },
+    spawn::{self, CommandOverride},
+    task::TaskSet,
+    version,
+};
+#[cfg(feature = "systemd")]
+use sd_notify::NotifyState;
+use tokio::{
+    net::UnixListener,
+    signal::unix::{self, SignalKind},
+    sync::{mpsc, OwnedSemaphorePermit, Semaphore},
+    time,
 };
-use tokio::{process, sync::mpsc};
 use tokio_stream::wrappers::ReceiverStream;
 use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
 use tracing::Instrument;
+use ulid::Ulid;
 
 /// Git remote utils credential helper server
+///
+/// Sending SIGUSR2 to the server upgrades it in place: the program is started again from the
+/// same path with the same arguments and takes the sockets over, while the old server finishes
+/// its running sessions and exits.
 #[derive(Debug, clap::Parser)]
-#[clap(author, version, about)]
+#[clap(author, version, long_version = version::LONG_VERSION, about)]
 struct Args {
     /// internet socket address (address:port) or Unix socket address (path)
+    ///
+    /// Not needed when the socket is passed by systemd socket activation.
     #[clap(
         short,
         long = "bind",
         value_name = "ADDRESS",
         env = "GRU_CREDENTIAL_HELPER_BIND_ADDR"
     )]
-    bind_addr: String,
+    bind_addr: Option<String>,
+    /// Exit after no session has been running for DURATION, so that a socket-activated server
+    /// is started again on demand
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = |s: &str| config::parse_duration("--idle-exit", s.into()),
+        env = "GRU_CREDENTIAL_HELPER_IDLE_EXIT"
+    )]
+    idle_exit: Option<Duration>,
+    /// Address of a listener answering health probes (`GET /healthz`, `GET /readyz` or `ping`)
+    #[clap(
+        long,
+        value_name = "ADDRESS",
+        env = "GRU_CREDENTIAL_HELPER_HEALTH_ADDR"
+    )]
+    health_addr: Option<String>,
+    /// Append a record of each finished session to this file, chained by hashes so that
+    /// tampering can be detected (see `gru-credential-helper-admin verify-audit-log`)
+    #[clap(long, value_name = "PATH", env = "GRU_CREDENTIAL_HELPER_AUDIT_LOG")]
+    audit_log: Option<PathBuf>,
+    /// Send session lifecycle events as JSON to TARGET, `http://HOST[:PORT]/PATH` (a webhook
+    /// receiving `POST` requests) or `nats://HOST[:PORT]/SUBJECT`, can be given more than once
+    #[clap(
+        long = "event-target",
+        value_name = "TARGET",
+        action = clap::ArgAction::Append,
+        value_delimiter = ',',
+        env = "GRU_CREDENTIAL_HELPER_EVENT_TARGET"
+    )]
+    event_targets: Vec<Target>,
+    /// Run in the background, detached from the terminal, once the sockets are bound
+    ///
+    /// Logs written to stderr are discarded from then on, use `--log-sink syslog` to keep them.
+    #[clap(long)]
+    daemonize: bool,
+    /// Write the pid of the server to PATH, locked while it runs, and refuse to start if another
+    /// server holds it
+    #[clap(long, value_name = "PATH", env = "GRU_CREDENTIAL_HELPER_PIDFILE")]
+    pidfile: Option<PathBuf>,
+    /// Path of a Unix socket accepting admin requests (see gru-credential-helper-admin)
+    #[clap(long, value_name = "PATH", env = "GRU_CREDENTIAL_HELPER_ADMIN_SOCKET")]
+    admin_socket: Option<PathBuf>,
+    /// Require clients to sign a challenge with a key listed in this OpenSSH `authorized_keys`
+    /// file, using their ssh-agent
+    ///
+    /// The file is read again for each connection, so keys can be added or revoked without
+    /// restarting the server. Keys with options (e.g. `from=`) are rejected.
+    #[cfg(feature = "ssh-agent-auth")]
+    #[clap(
+        long,
+        value_name = "PATH",
+        env = "GRU_CREDENTIAL_HELPER_AUTHORIZED_KEYS"
+    )]
+    authorized_keys: Option<PathBuf>,
+    /// Run COMMAND through `sh -c` instead of `git credential` for OPERATION (`get`, `store` or
+    /// `erase`), with `%o` replaced with the `git credential` operation and `%c` with OPERATION
+    #[clap(
+        long = "command",
+        value_name = "OPERATION=COMMAND",
+        action = clap::ArgAction::Append
+    )]
+    command_overrides: Vec<CommandOverride>,
+    /// Close connections that haven't sent their request within DURATION, handshake and
+    /// authentication included
+    #[clap(
+        long,
+        value_name = "DURATION",
+        default_value = "10s",
+        value_parser = |s: &str| config::parse_duration("--handshake-timeout", s.into()),
+        env = "GRU_CREDENTIAL_HELPER_HANDSHAKE_TIMEOUT"
+    )]
+    handshake_timeout: Duration,
+    /// Number of connections that may be waiting to send their request at once; more are closed
+    /// right away
+    #[clap(
+        long,
+        value_name = "COUNT",
+        default_value = "64",
+        env = "GRU_CREDENTIAL_HELPER_MAX_PENDING"
+    )]
+    max_pending: NonZeroUsize,
+    /// Size of the buffers used to read the output of git
+    #[clap(
+        long,
+        value_name = "BYTES",
+        default_value_t = DEFAULT_BUFFER_SIZE,
+        value_parser = |s: &str| config::parse_buffer_size("--buffer-size", s.into()),
+        env = "GRU_CREDENTIAL_HELPER_BUFFER_SIZE"
+    )]
+    buffer_size: usize,
+    /// Number of chunks each stdio channel can hold before applying backpressure
+    #[clap(
+        long,
+        value_name = "COUNT",
+        default_value = "1",
+        env = "GRU_CREDENTIAL_HELPER_CHANNEL_DEPTH"
+    )]
+    channel_depth: NonZeroUsize,
+    /// Maximum number of bytes buffered across all sessions
+    ///
+    /// Sessions that don't fit in the budget wait until other sessions finish.
+    #[clap(
+        long,
+        value_name = "BYTES",
+        env = "GRU_CREDENTIAL_HELPER_MEMORY_BUDGET"
+    )]
+    memory_budget: Option<usize>,
+    /// Number of interactive sessions admitted for each batch session when both are waiting for
+    /// the memory budget
+    #[clap(
+        long,
+        value_name = "COUNT",
+        default_value = "4",
+        env = "GRU_CREDENTIAL_HELPER_INTERACTIVE_WEIGHT"
+    )]
+    interactive_weight: NonZeroUsize,
+    /// statsd server (host:port) to send metrics to over UDP
+    #[clap(
+        long,
+        value_name = "ADDRESS",
+        env = "GRU_CREDENTIAL_HELPER_STATSD_ADDR"
+    )]
+    statsd_addr: Option<String>,
+    /// Prometheus Pushgateway (http://host[:port][/path]) to push metrics to
+    #[clap(long, value_name = "URL", env = "GRU_CREDENTIAL_HELPER_PUSH_GATEWAY")]
+    push_gateway: Option<PushGateway>,
+    /// Prefix of the metric names sent to statsd or the Pushgateway, and job name of the latter
+    #[clap(
+        long,
+        value_name = "PREFIX",
+        default_value = "gru",
+        env = "GRU_CREDENTIAL_HELPER_METRICS_PREFIX"
+    )]
+    metrics_prefix: String,
+    /// Interval between metric flushes and samples of the resource gauges (open files, budget)
+    #[clap(
+        long,
+        value_name = "DURATION",
+        default_value = "10s",
+        value_parser = |s: &str| config::parse_duration("--metrics-interval", s.into()),
+        env = "GRU_CREDENTIAL_HELPER_METRICS_INTERVAL"
+    )]
+    metrics_interval: Duration,
+    /// User (name or id) to run as after binding sockets
+    ///
+    /// git is run as this user too, with `HOME` set to the user's home directory.
+    #[clap(long, value_name = "USER", env = "GRU_CREDENTIAL_HELPER_USER")]
+    user: Option<String>,
+    /// Group (name or id) to run as after binding sockets, defaults to the user's primary group
+    #[clap(long, value_name = "GROUP", env = "GRU_CREDENTIAL_HELPER_GROUP")]
+    group: Option<String>,
+    /// Niceness added to the priority of each git process
+    #[clap(
+        long,
+        value_name = "INCREMENT",
+        env = "GRU_CREDENTIAL_HELPER_CHILD_NICE"
+    )]
+    child_nice: Option<i32>,
+    /// I/O scheduling class of each git process (`idle`, `best-effort[:LEVEL]` or
+    /// `realtime[:LEVEL]`)
+    #[clap(long, value_name = "CLASS", env = "GRU_CREDENTIAL_HELPER_CHILD_IONICE")]
+    child_ionice: Option<IoPriority>,
+    /// Maximum size of the address space of each git process (`RLIMIT_AS`)
+    #[clap(
+        long,
+        value_name = "BYTES",
+        env = "GRU_CREDENTIAL_HELPER_CHILD_MEMORY_LIMIT"
+    )]
+    child_memory_limit: Option<u64>,
+    /// Maximum CPU time of each git process (`RLIMIT_CPU`, whole seconds)
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = |s: &str| config::parse_duration("--child-cpu-limit", s.into()),
+        env = "GRU_CREDENTIAL_HELPER_CHILD_CPU_LIMIT"
+    )]
+    child_cpu_limit: Option<Duration>,
+    /// cgroup (v2) directory each git process is moved into
+    #[clap(long, value_name = "PATH", env = "GRU_CREDENTIAL_HELPER_CHILD_CGROUP")]
+    child_cgroup: Option<PathBuf>,
+    /// Variable of the server's environment passed to git (`NAME`, or `PREFIX*` for every
+    /// variable starting with PREFIX), in addition to the defaults; `*` passes them all
+    ///
+    /// git runs in `/` with PATH, HOME, USER, LOGNAME, SHELL, LANG, LANGUAGE, LC_*, TZ, TMPDIR,
+    /// XDG_RUNTIME_DIR, DBUS_SESSION_BUS_ADDRESS and GIT_* only.
+    #[clap(
+        long = "child-env",
+        value_name = "NAME",
+        action = clap::ArgAction::Append,
+        value_delimiter = ',',
+        env = "GRU_CREDENTIAL_HELPER_CHILD_ENV"
+    )]
+    child_env: Vec<String>,
+    /// Increase log verbosity (`-v` debug, `-vv` trace)
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Where to write logs, can be given more than once [default: stderr]
+    #[clap(
+        long = "log-sink",
+        value_name = "SINK",
+        value_enum,
+        action = clap::ArgAction::Append,
+        value_delimiter = ',',
+        env = "GRU_CREDENTIAL_HELPER_LOG_SINK"
+    )]
+    log_sinks: Vec<log::Sink>,
+    /// Validate the configuration and exit without binding any socket
+    #[clap(long)]
+    check: bool,
+    /// Don't lock Unix sockets (on `PATH.lock`), and don't remove the ones left behind by a
+    /// server that is no longer running before binding them
+    #[clap(long)]
+    no_cleanup: bool,
+    /// Inject faults to test clients: comma-separated `reject-every=N` (close every Nth
+    /// connection), `stall-after=BYTES` (stop sending output) and `abort-after=BYTES` (close the
+    /// connection mid-output)
+    #[clap(
+        long,
+        value_name = "FAULTS",
+        default_value = "",
+        hide = true,
+        env = "GRU_CREDENTIAL_HELPER_TEST_FAULTS"
+    )]
+    test_faults: Faults,
 }
 
-#[tokio::main]
-async fn main() -> eyre::Result<()> {
+fn main() -> eyre::Result<()> {
     color_eyre::install()?;
-    tracing_subscriber::fmt::init();
 
-    let Args { bind_addr } = Args::parse();
+    let args = Args::parse();
+    let handover = Handover::from_env().wrap_err("invalid handover from the previous server")?;
+    // forked before the runtime starts its threads, and already detached when taking over
+    let daemon = (args.daemonize && !args.check && handover.is_none())
+        .then(daemon::daemonize)
+        .transpose()
+        .wrap_err("failed to daemonize")?;
+    tokio::runtime::Runtime::new()?.block_on(run(args, daemon, handover))
+}
 
-    let listener = SocketListener::bind(&bind_addr)
-        .await
-        .wrap_err_with(|| format!("failed to bind socket: {bind_addr}"))?;
+async fn run(
+    args: Args,
+    daemon: Option<Daemon>,
+    mut handover: Option<Handover>,
+) -> eyre::Result<()> {
+    let Args {
+        bind_addr,
+        idle_exit,
+        health_addr,
+        audit_log,
+        event_targets,
+        daemonize: _,
+        pidfile,
+        admin_socket,
+        #[cfg(feature = "ssh-agent-auth")]
+        authorized_keys,
+        command_overrides,
+        handshake_timeout,
+        max_pending,
+        buffer_size,
+        channel_depth,
+        memory_budget,
+        interactive_weight,
+        statsd_addr,
+        push_gateway,
+        metrics_prefix,
+        metrics_interval,
+        user,
+        group,
+        child_nice,
+        child_ionice,
+        child_memory_limit,
+        child_cpu_limit,
+        child_cgroup,
+        child_env,
+        verbose,
+        log_sinks,
+        check,
+        no_cleanup,
+        test_faults,
+    } = args;
+    // the server logs informational messages by default
+    let filter = (verbose > 0).then(|| log::verbosity_filter(verbose.saturating_add(2)));
+    let log_sinks = if log_sinks.is_empty() {
+        vec![log::Sink::Stderr]
+    } else {
+        log_sinks
+    };
+    log::init_sinks(filter, log::verbosity_filter(2), &log_sinks)?;
+
+    let user = user
+        .map(|user| Account::lookup_user(&user).wrap_err("invalid --user"))
+        .transpose()?;
+    let group = group
+        .map(|group| privilege::lookup_group(&group).wrap_err("invalid --group"))
+        .transpose()?;
 
-    for client_id in 0.. {
-        match listener.accept().await {
+    let env_allow_list = spawn::DEFAULT_ENV_ALLOW_LIST
+        .iter()
+        .map(|&name| name.to_owned())
+        .chain(child_env)
+        .collect::<Vec<_>>();
+
+    let mut child_limits = ChildLimits::default();
+    child_limits.nice = child_nice;
+    child_limits.io_priority = child_ionice;
+    child_limits.address_space = child_memory_limit;
+    // round up so that a limit below one second doesn't disable it
+    child_limits.cpu_time = child_cpu_limit.map(|limit| limit.as_secs().max(1));
+    if let Some(path) = &child_cgroup {
+        child_limits
+            .set_cgroup(path)
+            .wrap_err_with(|| format!("invalid cgroup: {}", path.display()))?;
+    }
+
+    #[cfg(not(feature = "ssh-agent-auth"))]
+    let authorized_keys = None;
+    #[cfg(feature = "ssh-agent-auth")]
+    if let Some(path) = &authorized_keys {
+        let keys = AuthorizedKeySet::read(path)
+            .wrap_err_with(|| format!("failed to read authorized keys: {}", path.display()))?;
+        if keys.is_empty() {
+            tracing::warn!(
+                "{} lists no keys, all clients will be rejected",
+                path.display()
+            );
+        } else {
+            tracing::info!("{} authorized keys", keys.len());
+        }
+    }
+
+    // opened before dropping privileges, so that the log can be kept out of reach of git
+    let audit_log = audit_log
+        .map(|path| {
+            match handover.as_mut().and_then(|h| h.take("audit-log")) {
+                Some(fd) => AuditLog::from_file(fd.into()),
+                None => AuditLog::open(&path),
+            }
+            .wrap_err_with(|| format!("failed to open audit log: {}", path.display()))
+        })
+        .transpose()?;
+
+    if !test_faults.is_empty() {
+        tracing::warn!("injecting faults: {test_faults:?}");
+    }
+
+    let limits = SessionLimits {
+        buffer_size,
+        channel_depth: channel_depth.get(),
+    };
+    let budget = match memory_budget {
+        Some(budget) => {
+            let cost = limits.cost();
+            if budget < cost {
+                bail!("memory budget {budget} is smaller than the cost of a single session ({cost} bytes)");
+            }
+            Some(Budget::new(budget, interactive_weight.get()))
+        }
+        None => None,
+    };
+
+    if check {
+        for addr in [&bind_addr, &health_addr, &statsd_addr]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .chain(push_gateway.iter().map(PushGateway::authority))
+            .chain(event_targets.iter().map(Target::authority))
+        {
+            let mut addrs = addr
+                .to_socket_addrs()
+                .await
+                .wrap_err_with(|| format!("failed to resolve address: {addr}"))?;
+            if addrs.next().is_none() {
+                bail!("address resolves to nothing: {addr}");
+            }
+        }
+        println!("configuration is valid");
+        return Ok(());
+    }
+
+    // held until the server exits
+    let pidfile = pidfile
+        .map(|path| {
+            match handover.as_mut().and_then(|h| h.take("pidfile")) {
+                Some(fd) => PidFile::adopt(&path, fd).map_err(Into::into),
+                None => PidFile::create(&path),
+            }
+            .wrap_err_with(|| format!("failed to create pidfile: {}", path.display()))
+        })
+        .transpose()?;
+    let mut socket_locks = handover
+        .as_mut()
+        .map(|h| {
+            h.take_all("lock")
+                .into_iter()
+                .map(SocketLock::from)
+                .collect()
+        })
+        .unwrap_or_default();
+    let handed_over = handover.as_mut().and_then(|h| h.take("bind"));
+    #[cfg(not(feature = "systemd"))]
+    let activated = None;
+    #[cfg(feature = "systemd")]
+    let activated = match handed_over {
+        Some(_) => None,
+        None => activated_listener()?,
+    };
+    let listener = match (handed_over, activated, bind_addr) {
+        (Some(fd), _, _) => unsafe { SocketListener::from_raw_fd(fd.into_raw_fd()) }
+            .wrap_err("invalid socket handed over")?,
+        (None, Some(listener), bind_addr) => {
+            if let Some(bind_addr) = bind_addr {
+                tracing::warn!("listening on the socket passed by systemd instead of {bind_addr}");
+            }
+            listener
+        }
+        (None, None, Some(bind_addr)) => bind(&bind_addr, no_cleanup, &mut socket_locks)
+            .await
+            .map_err(|e| {
+                let hint = hint::bind(&e, &bind_addr);
+                let report =
+                    eyre::Report::new(e).wrap_err(format!("failed to bind socket: {bind_addr}"));
+                match hint {
+                    Some(hint) => report.suggestion(hint),
+                    None => report,
+                }
+            })?,
+        (None, None, None) => {
+            bail!("no address to listen on (use --bind or GRU_CREDENTIAL_HELPER_BIND_ADDR)")
+        }
+    };
+
+    // tasks serving the other sockets, stopped once handed over
+    let mut listener_tasks = TaskSet::default();
+    // tasks running as long as the server
+    let mut background_tasks = TaskSet::default();
+    let mut handover_files = vec![("bind", listener.as_raw_fd())];
+
+    let health = Arc::new(Health::default());
+    if let Some(health_addr) = health_addr {
+        let listener = match handover.as_mut().and_then(|h| h.take("health")) {
+            Some(fd) => unsafe { SocketListener::from_raw_fd(fd.into_raw_fd()) }
+                .wrap_err("invalid health socket handed over")?,
+            None => bind(&health_addr, no_cleanup, &mut socket_locks)
+                .await
+                .map_err(|e| {
+                    let hint = hint::bind(&e, &health_addr);
+                    let report = eyre::Report::new(e)
+                        .wrap_err(format!("failed to bind health socket: {health_addr}"));
+                    match hint {
+                        Some(hint) => report.suggestion(hint),
+                        None => report,
+                    }
+                })?,
+        };
+        handover_files.push(("health", listener.as_raw_fd()));
+        listener_tasks.spawn(health::serve(listener, Arc::clone(&health)));
+    }
+
+    let metrics = Arc::new(Metrics::default());
+    if let Some(push_gateway) = push_gateway {
+        background_tasks.spawn(flush_metrics(
+            Box::new(push_gateway.prefix(&metrics_prefix)),
+            Arc::clone(&metrics),
+            metrics_interval,
+        ));
+    }
+    if let Some(statsd_addr) = statsd_addr {
+        let sink = Statsd::connect(&statsd_addr, metrics_prefix)
+            .await
+            .wrap_err_with(|| format!("failed to set up statsd sink: {statsd_addr}"))?;
+        background_tasks.spawn(flush_metrics(
+            Box::new(sink),
+            Arc::clone(&metrics),
+            metrics_interval,
+        ));
+    }
+
+    let mut sessions = Sessions::default();
+    if let Some(audit_log) = audit_log {
+        handover_files.push(("audit-log", audit_log.as_raw_fd()));
+        let writer = AuditWriter::spawn(audit_log).wrap_err("failed to start audit log writer")?;
+        sessions = sessions.with_audit_log(writer);
+    }
+    if !event_targets.is_empty() {
+        let emitters = event_targets
+            .into_iter()
+            .map(Target::into_emitter)
+            .collect();
+        sessions = sessions.with_events(EventQueue::spawn(emitters));
+    }
+    let sessions = Arc::new(sessions);
+    if let Some(path) = admin_socket {
+        let listener = match handover.as_mut().and_then(|h| h.take("admin")) {
+            Some(fd) => {
+                let listener = std::os::unix::net::UnixListener::from(fd);
+                listener.set_nonblocking(true)?;
+                UnixListener::from_std(listener).wrap_err("invalid admin socket handed over")?
+            }
+            None => {
+                if !no_cleanup {
+                    let lock = SocketLock::acquire(&path).wrap_err_with(|| {
+                        format!("failed to lock admin socket: {}", path.display())
+                    })?;
+                    socket_locks.push(lock);
+                }
+                UnixListener::bind(&path)
+                    .wrap_err_with(|| format!("failed to bind admin socket: {}", path.display()))?
+            }
+        };
+        handover_files.push(("admin", listener.as_raw_fd()));
+        listener_tasks.spawn(admin::serve(
+            listener,
+            Arc::clone(&sessions),
+            Arc::clone(&metrics),
+        ));
+    }
+    handover_files.extend(socket_locks.iter().map(|lock| ("lock", lock.as_raw_fd())));
+    if let Some(pidfile) = &pidfile {
+        handover_files.push(("pidfile", pidfile.as_raw_fd()));
+    }
+
+    // a server taking over runs with the privileges the previous one had dropped to
+    if (user.is_some() || group.is_some()) && handover.is_none() {
+        privilege::drop_privileges(user.as_ref(), group).wrap_err("failed to drop privileges")?;
+        tracing::info!(
+            "running as uid {} gid {}",
+            unsafe { libc::getuid() },
+            unsafe { libc::getgid() }
+        );
+    }
+
+    let shared = Arc::new(Shared {
+        limits,
+        handshake_timeout,
+        pending: Arc::new(Semaphore::new(max_pending.get())),
+        authorized_keys,
+        command_overrides,
+        env_allow_list,
+        child_limits,
+        budget,
+        metrics,
+        sessions,
+        faults: test_faults,
+    });
+
+    // everything opened from now on belongs to a session
+    match metrics::open_fds() {
+        Ok(baseline_fds) => {
+            background_tasks.spawn(reconcile(
+                Arc::clone(&shared),
+                baseline_fds,
+                metrics_interval,
+            ));
+        }
+        Err(e) => tracing::warn!("failed to count open files, leaks won't be detected: {e}"),
+    }
+
+    // the configuration has been validated and the listener is bound
+    health.set_ready(true);
+    #[cfg(feature = "systemd")]
+    notify_systemd(&mut background_tasks);
+    if let Some(daemon) = daemon {
+        if let Err(e) = daemon.ready() {
+            tracing::warn!("failed to detach from the terminal: {e}");
+        }
+    }
+    if let Some(mut handover) = handover.take() {
+        tracing::info!("took over from the previous server");
+        if let Err(e) = handover.ready() {
+            tracing::warn!("failed to tell the previous server to stop: {e}");
+        }
+    }
+    let mut upgrade =
+        unix::signal(SignalKind::user_defined2()).wrap_err("failed to handle SIGUSR2")?;
+    let mut upgrading = None;
+    let mut idle = pin!(async {
+        match idle_exit {
+            Some(timeout) => shared.sessions.wait_idle(timeout).await,
+            None => future::pending().await,
+        }
+    });
+    let mut handed_over_to = None;
+    let mut session_tasks = TaskSet::default();
+    let mut next_client_id = 0;
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            Some(res) = session_tasks.join_next(), if !session_tasks.is_empty() => {
+                if let Err(e) = res {
+                    tracing::error!("session task failed: {e}");
+                }
+                continue;
+            }
+            () = &mut idle => break,
+            Some(()) = upgrade.recv(), if upgrading.is_none() => {
+                tracing::info!("starting a new server to hand the sockets over to");
+                upgrading = Some(Box::pin(handover::spawn(&handover_files)));
+                continue;
+            }
+            res = async { upgrading.as_mut().unwrap().await }, if upgrading.is_some() => {
+                upgrading = None;
+                match res {
+                    Ok(pid) => {
+                        handed_over_to = Some(pid);
+                        break;
+                    }
+                    Err(e) => {
+                        tracing::error!("failed to hand over to a new server: {e}");
+                        continue;
+                    }
+                }
+            }
+        };
+        let client_id = next_client_id;
+        next_client_id += 1;
+        match accepted {
+            Ok((stream, addr)) if shared.faults.rejects(client_id) => {
+                tracing::warn!("injected fault: rejecting connection from {addr}");
+                drop(stream);
+            }
             Ok((stream, addr)) => {
-                tokio::spawn(
+                let Ok(pending) = Arc::clone(&shared.pending).try_acquire_owned() else {
+                    tracing::warn!("too many pending connections, closing connection from {addr}");
+                    drop(stream);
+                    continue;
+                };
+                let shared = Arc::clone(&shared);
+                shared.metrics.accepted.inc();
+                let session = shared.sessions.register(client_id, addr.to_string());
+                session_tasks.spawn(
                     async move {
                         tracing::info!("accepted connection from {}", addr);
-                        if let Err(e) = handle_client(stream).await {
+                        if let Err(e) = handle_client(stream, session, pending, &shared).await {
+                            shared.metrics.failed.inc();
                             tracing::error!("{e:?}");
                         }
                     }
@@ -59,29 +725,262 @@ async fn main() -> eyre::Result<()> {
         }
     }
 
-    unreachable!()
+    if let Some(pid) = handed_over_to {
+        // the new server accepts the connections from now on
+        drop(listener);
+        listener_tasks.abort_all();
+        if let Some(pidfile) = pidfile {
+            pidfile.handed_over();
+        }
+        #[cfg(feature = "systemd")]
+        if let Err(e) = sd_notify::notify(false, &[NotifyState::MainPid(pid)]) {
+            tracing::warn!("failed to notify systemd: {e}");
+        }
+        tracing::info!(
+            "handed over to pid {pid}, waiting for {} sessions to finish",
+            session_tasks.len()
+        );
+        if let Err(e) = session_tasks.join_all().await {
+            tracing::error!("session task failed: {e}");
+        }
+        tracing::info!("exiting after handing over");
+        return Ok(());
+    }
+
+    // no session is running, and connections queued on a socket passed by systemd start the
+    // server again
+    tracing::info!(
+        "exiting after being idle for {:?}",
+        idle_exit.unwrap_or_default()
+    );
+    #[cfg(feature = "systemd")]
+    if let Err(e) = sd_notify::notify(false, &[NotifyState::Stopping]) {
+        tracing::warn!("failed to notify systemd: {e}");
+    }
+    Ok(())
+}
+
+/// Binds `addr`, first locking it and removing the stale socket found there if it's a Unix socket
+/// (see [`SocketLock`]), unless `no_cleanup`.
+async fn bind(
+    addr: &str,
+    no_cleanup: bool,
+    locks: &mut Vec<SocketLock>,
+) -> io::Result<SocketListener> {
+    if !no_cleanup {
+        if let Some(Ok(SocketAddr::UnixStd(unix_addr))) = SocketAddr::parse_unix(addr) {
+            if let Some(path) = unix_addr.as_pathname() {
+                locks.push(SocketLock::acquire(path)?);
+            }
+        }
+    }
+    SocketListener::bind(addr).await
+}
+
+/// Takes the listening socket passed by systemd, when started by a socket unit.
+#[cfg(feature = "systemd")]
+fn activated_listener() -> eyre::Result<Option<SocketListener>> {
+    let mut fds = sd_notify::listen_fds().wrap_err("invalid socket activation environment")?;
+    let Some(fd) = fds.next() else {
+        return Ok(None);
+    };
+    if fds.next().is_some() {
+        bail!("systemd passed more than one socket, only one is supported");
+    }
+    // systemd passes the socket to this process only, and it is taken over once
+    let listener =
+        unsafe { SocketListener::from_raw_fd(fd) }.wrap_err("invalid socket passed by systemd")?;
+    Ok(Some(listener))
+}
+
+/// Tells systemd that the server is ready and keeps its watchdog fed, when run as a
+/// `Type=notify` service.
+#[cfg(feature = "systemd")]
+fn notify_systemd(tasks: &mut TaskSet<()>) {
+    if let Err(e) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        tracing::warn!("failed to notify systemd: {e}");
+    }
+    let mut usec = 0;
+    if sd_notify::watchdog_enabled(false, &mut usec) {
+        // ping twice per period, as recommended by sd_watchdog_enabled(3)
+        let period = Duration::from_micros(usec) / 2;
+        tracing::debug!("systemd watchdog enabled, pinging every {period:?}");
+        tasks.spawn(async move {
+            let mut interval = time::interval(period);
+            loop {
+                interval.tick().await;
+                if let Err(e) = sd_notify::notify(false, &[NotifyState::Watchdog]) {
+                    tracing::warn!("failed to ping systemd watchdog: {e}");
+                }
+            }
+        });
+    }
+}
+
+async fn flush_metrics(mut sink: Box<dyn Sink>, metrics: Arc<Metrics>, interval: Duration) {
+    let mut interval = time::interval(interval);
+    loop {
+        interval.tick().await;
+        if let Err(e) = sink.send(&metrics.snapshot()).await {
+            tracing::warn!("failed to send metrics: {e}");
+        }
+    }
+}
+
+/// Runs a task of a session, holding `guard` until it finishes.
+async fn tracked<F: Future>(guard: TaskGuard, future: F) -> F::Output {
+    let _guard = guard;
+    future.await
+}
+
+/// Samples the resource gauges every `interval`, and warns about resources held by no session.
+///
+/// Leaks are reported once they are seen twice in a row without any connection in between, so
+/// that sessions still winding down are not mistaken for leaks.
+async fn reconcile(shared: Arc<Shared>, baseline_fds: i64, interval: Duration) {
+    let Shared {
+        budget,
+        metrics,
+        sessions,
+        ..
+    } = &*shared;
+    let mut interval = time::interval(interval);
+    let mut previous = None;
+    loop {
+        interval.tick().await;
+        match metrics::open_fds() {
+            Ok(fds) => metrics.open_fds.set(fds),
+            Err(e) => tracing::debug!("failed to count open files: {e}"),
+        }
+        if let Some(budget) = budget {
+            metrics
+                .budget_used
+                .set(budget.used().try_into().unwrap_or(i64::MAX));
+        }
+
+        let accepted = metrics.accepted.get();
+        let leaks = sessions
+            .list()
+            .is_empty()
+            .then(|| Leaks::find(metrics, baseline_fds))
+            .filter(|leaks| !leaks.is_empty());
+        if let (Some(leaks), Some((previous_accepted, Some(_)))) = (leaks, previous) {
+            if previous_accepted == accepted {
+                tracing::warn!("resources outlived their sessions: {leaks:?}");
+            }
+        }
+        previous = Some((accepted, leaks));
+    }
+}
+
+/// State shared by all sessions.
+#[derive(Debug)]
+struct Shared {
+    limits: SessionLimits,
+    /// Time a connection has to send its request
+    handshake_timeout: Duration,
+    /// Permits of the connections that haven't sent their request yet
+    pending: Arc<Semaphore>,
+    authorized_keys: Option<PathBuf>,
+    command_overrides: Vec<CommandOverride>,
+    /// Variables passed to git, see [`spawn::is_allowed`]
+    env_allow_list: Vec<String>,
+    child_limits: ChildLimits,
+    budget: Option<Arc<Budget>>,
+    metrics: Arc<Metrics>,
+    sessions: Arc<Sessions>,
+    faults: Faults,
+}
+
+/// Tags the logs of the session with `session_id`, the id the client shows in its errors.
+fn set_session_id(session: &SessionHandle, session_id: Ulid) {
+    tracing::Span::current().record("session_id", tracing::field::display(session_id));
+    tracing::info!("session id {session_id}");
+    session.update(|info| info.session_id = Some(session_id));
 }
 
-#[tracing::instrument(level = "info", err, ret, skip_all)]
-async fn handle_client(stream: SocketStream) -> eyre::Result<()> {
+#[derive(Debug, Clone, Copy)]
+struct SessionLimits {
+    buffer_size: usize,
+    channel_depth: usize,
+}
+
+impl SessionLimits {
+    /// Upper bound of the bytes buffered by a session.
+    ///
+    /// Each of the three stdio pumps holds a read buffer or up to `channel_depth` chunks.
+    fn cost(&self) -> usize {
+        3 * self.buffer_size * self.channel_depth
+    }
+}
+
+#[tracing::instrument(level = "info", err, ret, skip_all, fields(session_id = tracing::field::Empty))]
+async fn handle_client(
+    stream: SocketStream,
+    session: SessionHandle,
+    pending: OwnedSemaphorePermit,
+    shared: &Shared,
+) -> eyre::Result<()> {
+    let Shared {
+        limits,
+        handshake_timeout,
+        pending: _,
+        authorized_keys: _,
+        command_overrides,
+        env_allow_list,
+        child_limits,
+        budget,
+        metrics,
+        sessions: _,
+        faults,
+    } = shared;
+    let SessionLimits {
+        buffer_size,
+        channel_depth,
+    } = *limits;
     let (read_stream, write_stream) = stream.into_split();
-    let mut read_stream = FramedRead::new(read_stream, LengthDelimitedCodec::new());
-    let write_stream = FramedWrite::new(write_stream, LengthDelimitedCodec::new());
+    let mut read_stream = FramedRead::new(read_stream, protocol::codec(MAX_HANDSHAKE_FRAME_LENGTH));
+    let mut write_stream = FramedWrite::new(write_stream, protocol::codec(MAX_FRAME_LENGTH));
 
-    let SpawnMessage { command } = protocol::new_receiver(&mut read_stream)
-        .try_next()
+    let request = receive_request(&mut read_stream, &mut write_stream, &session, shared);
+    let SpawnMessage { command, priority } = time::timeout(*handshake_timeout, request)
         .await
-        .wrap_err("failed to receive message")?
-        .ok_or_else(|| eyre!("client sent no request"))?;
+        .map_err(|_| eyre!("client sent no request within {handshake_timeout:?}"))??;
+    drop(pending);
+    let priority = priority.unwrap_or_default();
+    read_stream
+        .decoder_mut()
+        .set_max_frame_length(MAX_FRAME_LENGTH);
 
-    tracing::debug!("received request: {:?}", command);
+    tracing::debug!("received request: {:?} ({:?})", command, priority);
+    session.update(|info| {
+        info.command = Some(command);
+        info.priority = Some(priority);
+    });
 
-    let mut cmd = process::Command::new("git");
-    match command {
-        Command::Get => cmd.args(&["credential", "fill"]),
-        Command::Store => cmd.args(&["credential", "approve"]),
-        Command::Erase => cmd.args(&["credential", "reject"]),
+    let cancel = session.cancel_token().clone();
+    let permit = match budget {
+        Some(budget) => {
+            let cost = limits.cost();
+            if !budget.is_available(cost) {
+                metrics.budget_waits.inc();
+                tracing::info!("waiting for memory budget ({priority:?})");
+            }
+            tokio::select! {
+                permit = budget.acquire(cost, priority) => Some(permit),
+                () = cancel.cancelled() => bail!("session killed while waiting for memory budget"),
+            }
+        }
+        None => None,
     };
+
+    let mut cmd = spawn::command(command, command_overrides)?;
+    spawn::scrub_env(&mut cmd, env_allow_list);
+    // git must not talk to systemd on behalf of the server
+    for name in ["NOTIFY_SOCKET", "WATCHDOG_PID", "WATCHDOG_USEC"] {
+        cmd.env_remove(name);
+    }
+    child_limits.apply(&mut cmd);
     cmd.stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
@@ -93,14 +992,31 @@ async fn handle_client(stream: SocketStream) -> eyre::Result<()> {
     let stderr = child.stderr.take().unwrap();
 
     tracing::debug!("spawned child process: {:?}", child.id());
+    session.update(|info| info.pid = child.id());
+    session.emit_started();
+    metrics.spawned.inc();
+    metrics.active.inc();
 
     let receiver = protocol::new_receiver::<_, ClientMessage>(read_stream);
     let mut sender = protocol::new_sender::<_, ServerMessage>(write_stream);
 
+    // the tasks of the session end with it, or are aborted if it fails
+    let mut tasks = TaskSet::default();
     let (exit_tx, exit_rx) = oneshot::channel();
-    tokio::spawn(
+    tasks.spawn(tracked(
+        metrics.track_task(),
         async move {
-            let exit = match child.wait().await {
+            let status = tokio::select! {
+                status = child.wait() => status,
+                () = cancel.cancelled() => {
+                    tracing::info!("killing child process");
+                    match child.kill().await {
+                        Ok(()) => child.wait().await,
+                        Err(e) => Err(e),
+                    }
+                }
+            };
+            let exit = match status {
                 Ok(status) => {
                     if let Some(code) = status.code() {
                         tracing::debug!("child process exited with code: {}", code);
@@ -117,71 +1033,257 @@ async fn handle_client(stream: SocketStream) -> eyre::Result<()> {
                     Exit::OtherError(e.to_string())
                 }
             };
-            exit_tx.send(exit).unwrap();
+            // the session stops listening if the client has gone away
+            let _ = exit_tx.send(exit);
+            Ok(())
         }
         .in_current_span()
         .instrument(tracing::info_span!("exit")),
-    );
+    ));
 
-    let (stdin_bytes_tx, stdin_bytes_rx) = mpsc::channel(1);
-    let (stdin_res_tx, stdin_res_rx) = mpsc::channel(1);
-    tokio::spawn(
+    let (stdin_bytes_tx, stdin_bytes_rx) = mpsc::channel(channel_depth);
+    let (stdin_res_tx, stdin_res_rx) = mpsc::channel(channel_depth);
+    tasks.spawn(tracked(
+        metrics.track_task(),
         gru::task::output(stdin, stdin_res_tx, stdin_bytes_rx)
             .in_current_span()
             .instrument(tracing::info_span!("stdin")),
-    );
+    ));
 
-    let (stdout_bytes_tx, stdout_bytes_rx) = mpsc::channel(1);
-    let (stdout_res_tx, stdout_res_rx) = mpsc::channel(1);
-    tokio::spawn(
-        gru::task::input(stdout, stdout_bytes_tx, stdout_res_rx)
+    let (stdout_bytes_tx, stdout_bytes_rx) = mpsc::channel(channel_depth);
+    let (stdout_res_tx, stdout_res_rx) = mpsc::channel(channel_depth);
+    tasks.spawn(tracked(
+        metrics.track_task(),
+        gru::task::input(stdout, buffer_size, stdout_bytes_tx, stdout_res_rx)
             .in_current_span()
             .instrument(tracing::info_span!("stdout")),
-    );
+    ));
 
-    let (stderr_bytes_tx, stderr_bytes_rx) = mpsc::channel(1);
-    let (stderr_res_tx, stderr_res_rx) = mpsc::channel(1);
-    tokio::spawn(
-        gru::task::input(stderr, stderr_bytes_tx, stderr_res_rx)
+    let (stderr_bytes_tx, stderr_bytes_rx) = mpsc::channel(channel_depth);
+    let (stderr_res_tx, stderr_res_rx) = mpsc::channel(channel_depth);
+    tasks.spawn(tracked(
+        metrics.track_task(),
+        gru::task::input(stderr, buffer_size, stderr_bytes_tx, stderr_res_rx)
             .in_current_span()
             .instrument(tracing::info_span!("stderr")),
-    );
+    ));
 
-    tokio::spawn(receive(receiver, stdin_bytes_tx, stdout_res_tx, stderr_res_tx).in_current_span());
-    tokio::spawn(
-        async move {
-            let exit = stream::once(exit_rx).map(|res| res.map(ServerMessage::Exit).unwrap());
-            let stdin = ReceiverStream::new(stdin_res_rx)
-                .map(OutputResponse)
-                .map(ServerMessage::Stdin);
-            let stdout = ReceiverStream::new(stdout_bytes_rx)
-                .map(OutputRequest::Output)
-                .chain(stream::once(future::ready(OutputRequest::Terminated)))
-                .map(ServerMessage::Stdout);
-            let stderr = ReceiverStream::new(stderr_bytes_rx)
-                .map(OutputRequest::Output)
-                .chain(stream::once(future::ready(OutputRequest::Terminated)))
-                .map(ServerMessage::Stderr);
-            let mut stream =
-                stream::select(exit, stream::select(stdin, stream::select(stdout, stderr)));
-            while let Some(msg) = stream.next().await {
-                tracing::trace!("sending message: {msg:?}");
-                match sender.send(msg).await {
-                    Ok(()) => tracing::trace!("message sent"),
-                    Err(e) => tracing::error!("failed to send message: {e:?}"),
+    // run by the session itself, and dropped with the connection once everything is sent
+    let mut receive = Box::pin(tracked(
+        metrics.track_task(),
+        receive(
+            receiver,
+            Arc::clone(session.traffic()),
+            stdin_bytes_tx,
+            stdout_res_tx,
+            stderr_res_tx,
+        )
+        .in_current_span(),
+    ));
+    let completed = send(
+        &mut sender,
+        &mut receive,
+        &session,
+        exit_rx,
+        stdin_res_rx,
+        stdout_bytes_rx,
+        stderr_bytes_rx,
+        *faults,
+    )
+    .instrument(tracing::info_span!("send"))
+    .await;
+    drop(receive);
+    drop(sender);
+
+    // the pumps of a failed session can be stuck on processes left behind by the command
+    if !completed {
+        tasks.abort_all();
+    }
+    let res = tasks.join_all().await;
+    // the session's buffers are released once everything is sent
+    drop(permit);
+    metrics.active.dec();
+    drop(session);
+    res.wrap_err("session task panicked")?;
+    Ok(())
+}
+
+/// Negotiates the connection, authenticates the client if required and receives its request.
+///
+/// The priority of the request is left out unless it was negotiated.
+async fn receive_request(
+    read_stream: &mut FramedRead<OwnedReadHalf, LengthDelimitedCodec>,
+    write_stream: &mut FramedWrite<OwnedWriteHalf, LengthDelimitedCodec>,
+    session: &SessionHandle,
+    shared: &Shared,
+) -> eyre::Result<SpawnMessage> {
+    let Shared {
+        authorized_keys,
+        #[cfg(feature = "ssh-agent-auth")]
+        metrics,
+        ..
+    } = shared;
+    let mut frame = read_stream
+        .try_next()
+        .await
+        .wrap_err("failed to receive message")?
+        .ok_or_else(|| eyre!("client sent no request"))?;
+    let mut priority_negotiated = false;
+    match protocol::decode_hello::<ClientHello>(&frame) {
+        Some(hello) => {
+            let hello = hello.wrap_err("invalid handshake")?;
+            tracing::debug!("received handshake: {hello:?}");
+            let features = FEATURES
+                .iter()
+                .copied()
+                .filter(|&feature| feature != AUTH_FEATURE || authorized_keys.is_some())
+                .collect::<Vec<_>>();
+            let reply = hello.negotiate_with(&features).and_then(|reply| {
+                if authorized_keys.is_some() && !reply.has_feature(AUTH_FEATURE) {
+                    return Err(
+                        "server requires authentication, which the client doesn't support".into(),
+                    );
                 }
+                Ok(reply)
+            });
+            session.update(|info| info.url = hello.url);
+            write_stream
+                .send(protocol::encode_hello(&reply)?)
+                .await
+                .wrap_err("failed to send handshake")?;
+            let reply = reply.map_err(|reason| eyre!("rejected handshake: {reason}"))?;
+            tracing::debug!("negotiated: {reply:?}");
+            priority_negotiated = reply.has_feature(PRIORITY_FEATURE);
+            let session_id = if reply.has_feature(SESSION_ID_FEATURE) {
+                let frame = read_stream
+                    .try_next()
+                    .await
+                    .wrap_err("failed to receive session id")?
+                    .ok_or_else(|| eyre!("client sent no session id"))?;
+                let SessionHello { session_id } = protocol::decode_hello(&frame)
+                    .ok_or_else(|| eyre!("client sent no session id"))?
+                    .wrap_err("invalid session id")?;
+                session_id
+            } else {
+                Ulid::new()
+            };
+            set_session_id(session, session_id);
+            #[cfg(feature = "ssh-agent-auth")]
+            if let Some(path) = authorized_keys {
+                let keys = AuthorizedKeySet::read(path).wrap_err_with(|| {
+                    format!("failed to read authorized keys: {}", path.display())
+                })?;
+                let key = auth::verify_client(read_stream, write_stream, &keys, metrics).await?;
+                tracing::info!("authenticated with key {key}");
+                session.update(|info| info.key = Some(key));
             }
+            frame = read_stream
+                .try_next()
+                .await
+                .wrap_err("failed to receive message")?
+                .ok_or_else(|| eyre!("client sent no request"))?;
         }
-        .in_current_span()
-        .instrument(tracing::info_span!("send")),
-    );
+        None if authorized_keys.is_some() => {
+            bail!("client sent no handshake, but authentication is required")
+        }
+        // clients before the handshake was introduced start with the request
+        None => {
+            tracing::debug!("client sent no handshake");
+            set_session_id(session, Ulid::new());
+        }
+    }
+    let SpawnMessage { command, priority } =
+        protocol::decode(&frame).wrap_err("failed to decode request")?;
+    // a priority the client was not asked for is ignored
+    let priority = priority.filter(|_| priority_negotiated);
+    Ok(SpawnMessage { command, priority })
+}
 
-    Ok(())
+/// Sends the exit status and the output of the command to the client, until everything is sent.
+///
+/// Returns `false` if the session failed before that.
+#[allow(clippy::too_many_arguments)]
+async fn send(
+    sender: &mut (impl futures::Sink<ServerMessage, Error = io::Error> + Unpin),
+    receive: &mut (impl Future + Unpin),
+    session: &SessionHandle,
+    exit_rx: oneshot::Receiver<Exit>,
+    stdin_res_rx: mpsc::Receiver<Result<(), String>>,
+    stdout_bytes_rx: mpsc::Receiver<Arc<BytesMut>>,
+    stderr_bytes_rx: mpsc::Receiver<Arc<BytesMut>>,
+    faults: Faults,
+) -> bool {
+    let mut sent = 0;
+    let exit = stream::once(exit_rx).map(|res| res.map(ServerMessage::Exit).unwrap());
+    let stdin = ReceiverStream::new(stdin_res_rx)
+        .map(OutputResponse)
+        .map(ServerMessage::Stdin);
+    let stdout = ReceiverStream::new(stdout_bytes_rx)
+        .map(OutputRequest::Output)
+        .chain(stream::once(future::ready(OutputRequest::Terminated)))
+        .map(ServerMessage::Stdout);
+    let stderr = ReceiverStream::new(stderr_bytes_rx)
+        .map(OutputRequest::Output)
+        .chain(stream::once(future::ready(OutputRequest::Terminated)))
+        .map(ServerMessage::Stderr);
+    let mut stream = stream::select(exit, stream::select(stdin, stream::select(stdout, stderr)));
+    let mut open_outputs = 2;
+    let mut client_gone = false;
+    loop {
+        let msg = tokio::select! {
+            msg = stream.next() => match msg {
+                Some(msg) => msg,
+                None => return true,
+            },
+            _ = &mut *receive, if !client_gone => {
+                client_gone = true;
+                // clients close the connection once they have received all the output
+                if open_outputs > 0 {
+                    tracing::info!("client went away, killing the command");
+                    session.cancel_token().cancel();
+                }
+                continue;
+            }
+        };
+        tracing::trace!("sending message: {msg:?}");
+        match &msg {
+            ServerMessage::Stdout(OutputRequest::Output(bytes))
+            | ServerMessage::Stderr(OutputRequest::Output(bytes)) => {
+                if let Some(fault) = faults.output_fault(sent) {
+                    tracing::warn!("injected fault: {fault:?} after {sent} bytes");
+                    if fault == OutputFault::Stall {
+                        // until the client gives up or the session is killed
+                        tokio::select! {
+                            _ = &mut *receive, if !client_gone => {}
+                            () = session.cancel_token().cancelled() => {}
+                        }
+                    }
+                    session.cancel_token().cancel();
+                    return false;
+                }
+                sent += bytes.len() as u64;
+                session.traffic().add_sent(bytes.len());
+            }
+            ServerMessage::Stdout(OutputRequest::Terminated)
+            | ServerMessage::Stderr(OutputRequest::Terminated) => open_outputs -= 1,
+            ServerMessage::Exit(exit) => session.update(|info| info.exit = Some(exit.clone())),
+            _ => {}
+        }
+        match sender.send(msg).await {
+            Ok(()) => tracing::trace!("message sent"),
+            Err(e) => {
+                tracing::error!("failed to send message: {e:?}");
+                session.cancel_token().cancel();
+                return false;
+            }
+        }
+    }
 }
 
 #[tracing::instrument(level = "debug", err, ret, skip_all)]
 async fn receive(
     mut receiver: impl TryStreamExt<Ok = ClientMessage, Error = io::Error> + Unpin,
+    traffic: Arc<Traffic>,
     stdin_tx: mpsc::Sender<Arc<BytesMut>>,
     stdout_tx: mpsc::Sender<Result<(), String>>,
     stderr_tx: mpsc::Sender<Result<(), String>>,
@@ -196,9 +1298,10 @@ async fn receive(
         match msg {
             ClientMessage::Stdin(msg) => match msg {
                 OutputRequest::Output(msg) => {
+                    traffic.add_received(msg.len());
                     stdin_tx
                         .as_mut()
-                        .unwrap()
+                        .ok_or_else(|| eyre!("client sent stdin after closing it"))?
                         .send(msg)
                         .await
                         .wrap_err("failed to send message")?;
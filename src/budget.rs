@@ -0,0 +1,142 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::oneshot;
+
+use crate::protocol::Priority;
+
+/// Memory budget shared by all sessions of a server.
+///
+/// Sessions that don't fit wait in a queue per priority. When both queues have waiters,
+/// `interactive_weight` interactive sessions are admitted for every batch session, so batch jobs
+/// can't starve interactive users but still make progress.
+#[derive(Debug)]
+pub struct Budget {
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    total: usize,
+    available: usize,
+    interactive_weight: usize,
+    interactive_streak: usize,
+    interactive: VecDeque<Waiter>,
+    batch: VecDeque<Waiter>,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    cost: usize,
+    tx: oneshot::Sender<Permit>,
+}
+
+/// Part of the budget held by a session, returned on drop.
+#[derive(Debug)]
+pub struct Permit {
+    budget: Arc<Budget>,
+    cost: usize,
+}
+
+impl Budget {
+    pub fn new(total: usize, interactive_weight: usize) -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(State {
+                total,
+                available: total,
+                interactive_weight,
+                interactive_streak: 0,
+                interactive: VecDeque::new(),
+                batch: VecDeque::new(),
+            }),
+        })
+    }
+
+    /// Returns whether a session of `cost` bytes can be admitted without waiting.
+    pub fn is_available(&self, cost: usize) -> bool {
+        let state = self.state.lock().unwrap();
+        state.interactive.is_empty() && state.batch.is_empty() && state.available >= cost
+    }
+
+    /// Bytes currently held by sessions.
+    pub fn used(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.total - state.available
+    }
+
+    /// Waits until `cost` bytes are available for a session of the given priority.
+    pub async fn acquire(self: &Arc<Self>, cost: usize, priority: Priority) -> Permit {
+        let rx = {
+            let mut state = self.state.lock().unwrap();
+            if state.interactive.is_empty() && state.batch.is_empty() && state.available >= cost {
+                state.available -= cost;
+                return Permit {
+                    budget: Arc::clone(self),
+                    cost,
+                };
+            }
+            let (tx, rx) = oneshot::channel();
+            let queue = match priority {
+                Priority::Interactive => &mut state.interactive,
+                Priority::Batch => &mut state.batch,
+            };
+            queue.push_back(Waiter { cost, tx });
+            rx
+        };
+        rx.await.expect("budget must outlive its waiters")
+    }
+
+    fn release(self: &Arc<Self>, cost: usize) {
+        let mut granted = vec![];
+        {
+            let mut state = self.state.lock().unwrap();
+            state.available += cost;
+            debug_assert!(
+                state.available <= state.total,
+                "budget released more than was acquired"
+            );
+            while let Some(waiter) = state.pop_next() {
+                granted.push(waiter);
+            }
+        }
+        // permits of waiters that have gone away are returned by their drop
+        for Waiter { cost, tx } in granted {
+            let _ = tx.send(Permit {
+                budget: Arc::clone(self),
+                cost,
+            });
+        }
+    }
+}
+
+impl State {
+    /// Dequeues the next waiter that fits in the available budget.
+    fn pop_next(&mut self) -> Option<Waiter> {
+        let prefer_batch = self.interactive.is_empty()
+            || (!self.batch.is_empty() && self.interactive_streak >= self.interactive_weight);
+        let (queue, is_interactive) = if prefer_batch {
+            (&mut self.batch, false)
+        } else {
+            (&mut self.interactive, true)
+        };
+        if queue.front()?.cost > self.available {
+            return None;
+        }
+        let waiter = queue.pop_front()?;
+        self.available -= waiter.cost;
+        if is_interactive {
+            self.interactive_streak += 1;
+        } else {
+            self.interactive_streak = 0;
+        }
+        Some(waiter)
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.budget.release(self.cost);
+    }
+}
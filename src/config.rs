@@ -0,0 +1,254 @@
+use std::{
+    env,
+    io::{self, IsTerminal as _},
+    time::Duration,
+};
+
+use crate::{
+    git_config,
+    protocol::{Priority, MAX_BUFFER_SIZE},
+    transport::Transport,
+};
+
+/// Format of the reports printed by the command line tools.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable text
+    #[default]
+    Text,
+    /// JSON, with the same fields in every release
+    Json,
+}
+
+/// Default size of the buffer used to copy stdio.
+pub const DEFAULT_BUFFER_SIZE: usize = 4 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("invalid value for {name}: {value:?} ({reason})")]
+    InvalidValue {
+        name: &'static str,
+        value: String,
+        reason: String,
+    },
+    #[error("failed to read git config {name}")]
+    GitConfig {
+        name: &'static str,
+        #[source]
+        source: io::Error,
+    },
+    #[error("server address is not specified (use --connect, {CONNECT_ADDR_ENV} or gru.connect)")]
+    MissingConnectAddr,
+}
+
+const CONNECT_ADDR_ENV: &str = "GRU_CREDENTIAL_HELPER_CONNECT_ADDR";
+const DIAL_COMMAND_ENV: &str = "GRU_CREDENTIAL_HELPER_DIAL_COMMAND";
+const TIMEOUT_ENV: &str = "GRU_CREDENTIAL_HELPER_TIMEOUT";
+const BUFFER_SIZE_ENV: &str = "GRU_CREDENTIAL_HELPER_BUFFER_SIZE";
+const PRIORITY_ENV: &str = "GRU_CREDENTIAL_HELPER_PRIORITY";
+const IDENTITY_ENV: &str = "GRU_CREDENTIAL_HELPER_IDENTITY";
+const LOG_ENV: &str = "GRU_CREDENTIAL_HELPER_LOG";
+
+/// A single layer of client settings, with unset values left as `None`.
+///
+/// Layers are merged in the following order of precedence, the first one that sets a value wins:
+///
+/// | setting     | command line     | environment variable                  | git config        |
+/// |-------------|------------------|---------------------------------------|-------------------|
+/// | address     | `--connect`      | `GRU_CREDENTIAL_HELPER_CONNECT_ADDR`  | `gru.connect`     |
+/// | dial        | `--dial-command` | `GRU_CREDENTIAL_HELPER_DIAL_COMMAND`  | `gru.dialCommand` |
+/// | timeout     | `--timeout`      | `GRU_CREDENTIAL_HELPER_TIMEOUT`       | `gru.timeout`     |
+/// | buffer size | `--buffer-size`  | `GRU_CREDENTIAL_HELPER_BUFFER_SIZE`   | `gru.bufferSize`  |
+/// | priority    | `--priority`     | `GRU_CREDENTIAL_HELPER_PRIORITY`      | `gru.priority`    |
+/// | identity    | `--identity`     | `GRU_CREDENTIAL_HELPER_IDENTITY`      | `gru.identity`    |
+/// | log filter  | `-v`             | `GRU_CREDENTIAL_HELPER_LOG`           |                   |
+///
+/// git config values are looked up with `--get-urlmatch` against the credential's URL.
+/// When no priority is set, sessions started from a terminal are interactive and the others are
+/// batch.
+#[derive(Debug, Clone, Default)]
+pub struct ClientOptions {
+    pub connect_addr: Option<String>,
+    pub dial_command: Option<String>,
+    pub timeout: Option<Duration>,
+    pub buffer_size: Option<usize>,
+    pub priority: Option<Priority>,
+    pub identity: Option<String>,
+    pub log: Option<String>,
+}
+
+impl ClientOptions {
+    /// Reads settings from environment variables.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Ok(Self {
+            connect_addr: env_var(CONNECT_ADDR_ENV)?,
+            dial_command: env_var(DIAL_COMMAND_ENV)?,
+            timeout: env_var(TIMEOUT_ENV)?
+                .map(|value| parse_duration(TIMEOUT_ENV, value))
+                .transpose()?,
+            buffer_size: env_var(BUFFER_SIZE_ENV)?
+                .map(|value| parse_buffer_size(BUFFER_SIZE_ENV, value))
+                .transpose()?,
+            priority: env_var(PRIORITY_ENV)?
+                .map(|value| parse_priority(PRIORITY_ENV, value))
+                .transpose()?,
+            identity: env_var(IDENTITY_ENV)?,
+            log: env_var(LOG_ENV)?,
+        })
+    }
+
+    /// Reads settings from git config applicable to `url`.
+    pub async fn from_git_config(url: &str) -> Result<Self, ConfigError> {
+        Ok(Self {
+            connect_addr: git_config_value("gru.connect", url).await?,
+            dial_command: git_config_value("gru.dialCommand", url).await?,
+            timeout: git_config_value("gru.timeout", url)
+                .await?
+                .map(|value| parse_duration("gru.timeout", value))
+                .transpose()?,
+            buffer_size: git_config_value("gru.bufferSize", url)
+                .await?
+                .map(|value| parse_buffer_size("gru.bufferSize", value))
+                .transpose()?,
+            priority: git_config_value("gru.priority", url)
+                .await?
+                .map(|value| parse_priority("gru.priority", value))
+                .transpose()?,
+            identity: git_config_value("gru.identity", url).await?,
+            log: None,
+        })
+    }
+
+    /// Fills unset values with the ones from `other`.
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            connect_addr: self.connect_addr.or(other.connect_addr),
+            dial_command: self.dial_command.or(other.dial_command),
+            timeout: self.timeout.or(other.timeout),
+            buffer_size: self.buffer_size.or(other.buffer_size),
+            priority: self.priority.or(other.priority),
+            identity: self.identity.or(other.identity),
+            log: self.log.or(other.log),
+        }
+    }
+}
+
+/// Fully resolved client settings.
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub connect_addr: String,
+    pub dial_command: Option<String>,
+    pub timeout: Option<Duration>,
+    pub buffer_size: usize,
+    pub priority: Priority,
+    /// ssh-agent key to authenticate with, when the server requires it
+    pub identity: Option<String>,
+}
+
+impl TryFrom<ClientOptions> for ClientConfig {
+    type Error = ConfigError;
+
+    fn try_from(options: ClientOptions) -> Result<Self, Self::Error> {
+        Ok(Self {
+            connect_addr: options
+                .connect_addr
+                .ok_or(ConfigError::MissingConnectAddr)?,
+            dial_command: options.dial_command,
+            timeout: options.timeout,
+            buffer_size: options.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE),
+            priority: options.priority.unwrap_or_else(|| {
+                if io::stderr().is_terminal() {
+                    Priority::Interactive
+                } else {
+                    Priority::Batch
+                }
+            }),
+            identity: options.identity,
+        })
+    }
+}
+
+impl ClientConfig {
+    /// Transport reaching the server with the configured dial command and timeout.
+    pub fn transport(&self) -> Transport {
+        let mut builder = Transport::builder();
+        if let Some(command) = &self.dial_command {
+            builder = builder.dial_command(command);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        builder.build()
+    }
+}
+
+fn env_var(name: &'static str) -> Result<Option<String>, ConfigError> {
+    match env::var(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(value)) => Err(ConfigError::InvalidValue {
+            name,
+            value: value.to_string_lossy().into_owned(),
+            reason: "not valid unicode".into(),
+        }),
+    }
+}
+
+async fn git_config_value(name: &'static str, url: &str) -> Result<Option<String>, ConfigError> {
+    git_config::get_urlmatch(name, url)
+        .await
+        .map_err(|source| ConfigError::GitConfig { name, source })
+}
+
+/// Parses a duration such as `30`, `30s`, `500ms` or `2m`; a bare number means seconds.
+pub fn parse_duration(name: &'static str, value: String) -> Result<Duration, ConfigError> {
+    let (number, unit) = match value.find(|ch: char| !ch.is_ascii_digit()) {
+        Some(pos) => value.split_at(pos),
+        None => (value.as_str(), "s"),
+    };
+    let invalid = |reason: &str| ConfigError::InvalidValue {
+        name,
+        value: value.clone(),
+        reason: reason.into(),
+    };
+    let number = number
+        .parse::<u64>()
+        .map_err(|_| invalid("expected a number followed by an optional unit"))?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(number)),
+        "s" => Ok(Duration::from_secs(number)),
+        "m" => number
+            .checked_mul(60)
+            .map(Duration::from_secs)
+            .ok_or_else(|| invalid("duration is too long")),
+        _ => Err(invalid("unit must be one of `ms`, `s` or `m`")),
+    }
+}
+
+/// Parses a positive buffer size in bytes, up to [`MAX_BUFFER_SIZE`] so that chunks of stdio fit
+/// in a frame.
+pub fn parse_buffer_size(name: &'static str, value: String) -> Result<usize, ConfigError> {
+    let reason = match value.parse::<usize>() {
+        Ok(size) if size > MAX_BUFFER_SIZE => format!("must be at most {MAX_BUFFER_SIZE} bytes"),
+        Ok(size) if size > 0 => return Ok(size),
+        _ => "expected a positive number of bytes".into(),
+    };
+    Err(ConfigError::InvalidValue {
+        name,
+        value,
+        reason,
+    })
+}
+
+/// Parses a session priority, `interactive` or `batch`.
+pub fn parse_priority(name: &'static str, value: String) -> Result<Priority, ConfigError> {
+    match value.to_ascii_lowercase().as_str() {
+        "interactive" => Ok(Priority::Interactive),
+        "batch" => Ok(Priority::Batch),
+        _ => Err(ConfigError::InvalidValue {
+            name,
+            value,
+            reason: "expected `interactive` or `batch`".into(),
+        }),
+    }
+}
@@ -0,0 +1,68 @@
+/// Credential description passed from git to a credential helper.
+///
+/// Only the attributes needed to identify the remote are kept.
+#[derive(Debug, Clone, Default)]
+pub struct Description {
+    pub protocol: Option<String>,
+    pub host: Option<String>,
+    pub path: Option<String>,
+}
+
+impl Description {
+    /// Parses `key=value` lines as written by git to the helper's stdin.
+    pub fn parse(input: &[u8]) -> Self {
+        let mut desc = Self::default();
+        for line in String::from_utf8_lossy(input).lines() {
+            let (key, value) = match line.split_once('=') {
+                Some(kv) => kv,
+                None => continue,
+            };
+            match key {
+                "protocol" => desc.protocol = Some(value.to_owned()),
+                "host" => desc.host = Some(value.to_owned()),
+                "path" => desc.path = Some(value.to_owned()),
+                _ => {}
+            }
+        }
+        desc
+    }
+
+    /// Parses the URL of a remote (`protocol://host[/path]`), the inverse of [`url`](Self::url).
+    pub fn from_url(url: &str) -> Option<Self> {
+        let (protocol, rest) = url.split_once("://")?;
+        let (host, path) = match rest.split_once('/') {
+            Some((host, path)) => (host, Some(path.to_owned())),
+            None => (rest, None),
+        };
+        Some(Self {
+            protocol: Some(protocol.to_owned()),
+            host: Some(host.to_owned()),
+            path: path.filter(|path| !path.is_empty()),
+        })
+    }
+
+    /// Formats the description as `key=value` lines, as git writes it to helpers.
+    pub fn to_input(&self) -> Vec<u8> {
+        let mut input = String::new();
+        for (key, value) in [
+            ("protocol", &self.protocol),
+            ("host", &self.host),
+            ("path", &self.path),
+        ] {
+            if let Some(value) = value {
+                input.push_str(&format!("{key}={value}\n"));
+            }
+        }
+        input.into_bytes()
+    }
+
+    /// Returns the URL the credential is requested for, if known.
+    pub fn url(&self) -> Option<String> {
+        let protocol = self.protocol.as_deref()?;
+        let host = self.host.as_deref().unwrap_or_default();
+        match &self.path {
+            Some(path) => Some(format!("{protocol}://{host}/{path}")),
+            None => Some(format!("{protocol}://{host}")),
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::{
+    fs::File,
+    io::{self, Read as _, Write as _},
+    os::unix::prelude::{AsRawFd, FromRawFd},
+    process,
+};
+
+/// Daemonized process, holding the pipe to the process that started it.
+#[derive(Debug)]
+pub struct Daemon {
+    ready: File,
+}
+
+/// Detaches the process from its terminal by forking twice around `setsid`.
+///
+/// Returns in the daemon only. The process that was started waits until [`Daemon::ready`] is
+/// called and exits successfully then, or with status 1 if the daemon exits first. The stdio of
+/// the daemon is kept until then, so that startup errors are still shown.
+///
+/// Must be called before any thread is started, such as the workers of a tokio runtime.
+pub fn daemonize() -> io::Result<Daemon> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (mut read, write) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()),
+        0 => drop(read),
+        child => {
+            drop(write);
+            let mut status = 0;
+            unsafe { libc::waitpid(child, &mut status, 0) };
+            // the daemon holds the write end until it is ready or exits
+            let ready = read.read_exact(&mut [0]).is_ok();
+            process::exit(if ready { 0 } else { 1 });
+        }
+    }
+
+    // leaves the session, and the controlling terminal, of the shell
+    if unsafe { libc::setsid() } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // not a session leader anymore, so that no terminal it opens becomes its controlling one
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(Daemon { ready: write }),
+        _ => unsafe { libc::_exit(0) },
+    }
+}
+
+impl Daemon {
+    /// Redirects stdio to `/dev/null` and lets the process that started the daemon exit.
+    pub fn ready(mut self) -> io::Result<()> {
+        let null = File::options().read(true).write(true).open("/dev/null")?;
+        for fd in 0..=2 {
+            if unsafe { libc::dup2(null.as_raw_fd(), fd) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        self.ready.write_all(&[1])
+    }
+}
@@ -0,0 +1,59 @@
+use std::{io, process::Stdio};
+
+use tokio::process::{self, Child};
+
+use crate::{
+    socket::{OwnedReadHalf, OwnedWriteHalf},
+    template,
+};
+
+/// Expands the tokens in a dial command template.
+///
+/// Supported tokens are `%h` (host or socket path), `%p` (port), `%a` (the whole address) and
+/// `%%` (a literal `%`).
+pub fn expand(template: &str, addr: &str) -> io::Result<String> {
+    let (host, port) = split_host_port(addr);
+    template::expand(template, "dial command", |token| match token {
+        'h' => Some(host),
+        'p' => Some(port),
+        'a' => Some(addr),
+        _ => None,
+    })
+}
+
+fn split_host_port(addr: &str) -> (&str, &str) {
+    let addr = addr.strip_prefix("unix:").unwrap_or(addr);
+    if addr.contains('/') {
+        return (addr, "");
+    }
+    match addr.rsplit_once(':') {
+        Some((host, port)) => {
+            let host = host
+                .strip_prefix('[')
+                .and_then(|host| host.strip_suffix(']'))
+                .unwrap_or(host);
+            (host, port)
+        }
+        None => (addr, ""),
+    }
+}
+
+/// Spawns the dial command through `sh -c` and uses its stdio as the connection.
+///
+/// The returned child must be kept alive until the connection is no longer used.
+pub fn spawn(template: &str, addr: &str) -> io::Result<(Child, OwnedReadHalf, OwnedWriteHalf)> {
+    let command = expand(template, addr)?;
+    tracing::debug!("spawning dial command: {command}");
+
+    let mut child = process::Command::new("sh")
+        .args(["-c", &command])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .kill_on_drop(true)
+        .spawn()?;
+    let stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+
+    Ok((child, stdout.into(), stdin.into()))
+}
@@ -0,0 +1,242 @@
+use std::{io, str::FromStr, time::Duration};
+
+use async_trait::async_trait;
+use serde::Serialize;
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncReadExt as _, AsyncWriteExt as _, BufReader},
+    net::TcpStream,
+    sync::mpsc,
+    time,
+};
+
+use crate::{admin::SessionInfo, http, protocol::Exit};
+
+/// Time allowed to deliver an event to a single target.
+const EMIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest reply read from a NATS server for a single event, `INFO` lines included.
+const MAX_NATS_REPLY_LENGTH: u64 = 64 * 1024;
+
+/// Number of events kept waiting while targets are slow or unreachable.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Lifecycle event of a session, sent as a JSON object with the state of the session.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    /// The command of the session has been spawned
+    SessionStarted {
+        #[serde(flatten)]
+        session: SessionInfo,
+    },
+    /// The command ran to its end, whatever its exit status
+    SessionCompleted {
+        #[serde(flatten)]
+        session: SessionInfo,
+    },
+    /// The session ended before the command exited, or the command couldn't be run
+    SessionFailed {
+        #[serde(flatten)]
+        session: SessionInfo,
+    },
+}
+
+impl Event {
+    /// Event for a session that has ended, in the state `session`.
+    pub fn ended(session: SessionInfo) -> Self {
+        match session.exit {
+            Some(Exit::Code(_) | Exit::Signal(_)) => Self::SessionCompleted { session },
+            Some(Exit::OtherError(_)) | None => Self::SessionFailed { session },
+        }
+    }
+}
+
+/// Destination the server sends events to.
+#[async_trait]
+pub trait Emitter: Send {
+    async fn emit(&mut self, event: &Event) -> io::Result<()>;
+}
+
+/// Destination of events given on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// `http://HOST[:PORT]/PATH`, receiving each event in a `POST` request
+    Webhook { authority: String, path: String },
+    /// `nats://HOST[:PORT]/SUBJECT`, receiving each event as a message
+    Nats { authority: String, subject: String },
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, rest) = s
+            .split_once("://")
+            .ok_or("expected http://HOST[:PORT]/PATH or nats://HOST[:PORT]/SUBJECT")?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err(format!("no host in {s:?}"));
+        }
+        match scheme {
+            "http" => Ok(Self::Webhook {
+                authority: with_default_port(authority, 80),
+                path: path.to_owned(),
+            }),
+            "nats" => {
+                let subject = &path[1..];
+                if subject.is_empty() || subject.contains(|c: char| c.is_whitespace()) {
+                    return Err(format!("invalid NATS subject {subject:?}"));
+                }
+                Ok(Self::Nats {
+                    authority: with_default_port(authority, 4222),
+                    subject: subject.to_owned(),
+                })
+            }
+            _ => Err(format!(
+                "unsupported scheme {scheme:?}, expected `http` or `nats`"
+            )),
+        }
+    }
+}
+
+pub(crate) fn with_default_port(authority: &str, port: u16) -> String {
+    // the closing bracket of an IPv6 address comes after its colons
+    match authority.rsplit_once(':') {
+        Some((_, after)) if !after.contains(']') => authority.to_owned(),
+        _ => format!("{authority}:{port}"),
+    }
+}
+
+impl Target {
+    /// Address (`HOST:PORT`) connected to.
+    pub fn authority(&self) -> &str {
+        match self {
+            Self::Webhook { authority, .. } | Self::Nats { authority, .. } => authority,
+        }
+    }
+
+    pub fn into_emitter(self) -> Box<dyn Emitter> {
+        match self {
+            Self::Webhook { authority, path } => Box::new(Webhook { authority, path }),
+            Self::Nats { authority, subject } => Box::new(Nats { authority, subject }),
+        }
+    }
+}
+
+/// Emitter posting each event to an HTTP endpoint, over a new connection.
+#[derive(Debug)]
+pub struct Webhook {
+    authority: String,
+    path: String,
+}
+
+#[async_trait]
+impl Emitter for Webhook {
+    async fn emit(&mut self, event: &Event) -> io::Result<()> {
+        let body = serde_json::to_vec(event)?;
+        http::send(
+            &self.authority,
+            "POST",
+            &self.path,
+            "application/json",
+            &body,
+        )
+        .await
+    }
+}
+
+/// Emitter publishing each event to a NATS subject, over a new connection.
+///
+/// Servers requiring TLS or authentication are not supported.
+#[derive(Debug)]
+pub struct Nats {
+    authority: String,
+    subject: String,
+}
+
+#[async_trait]
+impl Emitter for Nats {
+    async fn emit(&mut self, event: &Event) -> io::Result<()> {
+        let payload = serde_json::to_vec(event)?;
+        let stream = TcpStream::connect(&self.authority).await?;
+        let (read_stream, mut write_stream) = stream.into_split();
+        let mut lines = BufReader::new(read_stream.take(MAX_NATS_REPLY_LENGTH)).lines();
+        match lines.next_line().await? {
+            Some(line) if line.starts_with("INFO ") => {}
+            line => return Err(unexpected_nats_reply(line)),
+        }
+
+        let connect = serde_json::json!({
+            "verbose": false,
+            "pedantic": false,
+            "name": "gru-credential-helper-server",
+            "lang": "rust",
+            "version": env!("CARGO_PKG_VERSION"),
+        });
+        let mut message = format!(
+            "CONNECT {connect}\r\nPUB {} {}\r\n",
+            self.subject,
+            payload.len()
+        )
+        .into_bytes();
+        message.extend_from_slice(&payload);
+        // the server answers PING once it has processed everything sent before
+        message.extend_from_slice(b"\r\nPING\r\n");
+        write_stream.write_all(&message).await?;
+
+        loop {
+            match lines.next_line().await? {
+                Some(line) if line == "PONG" => return Ok(()),
+                // the server may send these at any time
+                Some(line) if line.starts_with("INFO ") || line == "+OK" => {}
+                line => return Err(unexpected_nats_reply(line)),
+            }
+        }
+    }
+}
+
+fn unexpected_nats_reply(line: Option<String>) -> io::Error {
+    match line {
+        Some(line) => io::Error::other(format!("NATS server answered {line:?}")),
+        None => io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "NATS server closed the connection",
+        ),
+    }
+}
+
+/// Queue of events delivered in order by a background task.
+#[derive(Debug, Clone)]
+pub struct EventQueue {
+    sender: mpsc::Sender<Event>,
+}
+
+impl EventQueue {
+    /// Starts delivering events to all of `emitters`.
+    pub fn spawn(mut emitters: Vec<Box<dyn Emitter>>) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<Event>(QUEUE_CAPACITY);
+        tokio::spawn(async move {
+            while let Some(event) = receiver.recv().await {
+                for emitter in &mut emitters {
+                    match time::timeout(EMIT_TIMEOUT, emitter.emit(&event)).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => tracing::warn!("failed to emit event: {e}"),
+                        Err(_) => tracing::warn!("timed out emitting event"),
+                    }
+                }
+            }
+        });
+        Self { sender }
+    }
+
+    /// Queues `event`, dropping it if the queue is full so that sessions never wait for a slow
+    /// target.
+    pub fn send(&self, event: Event) {
+        if let Err(e) = self.sender.try_send(event) {
+            tracing::warn!("dropped event: {e}");
+        }
+    }
+}
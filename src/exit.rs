@@ -0,0 +1,103 @@
+use std::{
+    io::{self, Write as _},
+    panic,
+    process::{self, ExitCode},
+};
+
+use color_eyre::{config::HookBuilder, eyre};
+
+use crate::protocol::Exit;
+
+/// Category of a failure, attached to error reports with `wrap_err` and mapped to a stable exit
+/// code.
+///
+/// The codes follow `sysexits.h`, so they don't collide with the exit status of the `git` command
+/// run by the server, which the client passes through as is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum Failure {
+    /// The command line is invalid
+    #[error("invalid usage")]
+    Usage,
+    /// The configuration is invalid or incomplete
+    #[error("invalid configuration")]
+    Config,
+    /// The server could not be reached
+    #[error("failed to connect to the server")]
+    Connect,
+    /// The server rejected the client's keys
+    #[error("authentication failed")]
+    Auth,
+    /// The peer sent something unexpected
+    #[error("protocol error")]
+    Protocol,
+    /// Reading or writing stdio failed
+    #[error("transfer aborted")]
+    Transfer,
+    /// The server could not run `git`
+    #[error("server failed to run git")]
+    Remote,
+    /// A bug in this program
+    #[error("internal error")]
+    Internal,
+}
+
+impl Failure {
+    pub fn code(self) -> u8 {
+        match self {
+            Self::Usage => 64,
+            Self::Config => 78,
+            Self::Connect => 69,
+            Self::Auth => 77,
+            Self::Protocol => 76,
+            Self::Transfer => 74,
+            Self::Remote => 71,
+            Self::Internal => 70,
+        }
+    }
+}
+
+impl From<Failure> for ExitCode {
+    fn from(failure: Failure) -> Self {
+        failure.code().into()
+    }
+}
+
+/// Returns the exit code for an error report, based on the outermost [`Failure`] attached to it.
+pub fn report_code(report: &eyre::Report) -> ExitCode {
+    report
+        .downcast_ref::<Failure>()
+        .map_or(ExitCode::FAILURE, |failure| (*failure).into())
+}
+
+/// Returns the exit code mirroring how the `git` command run by the server exited.
+///
+/// Termination by a signal is reported as `128 + signal`, like shells do.
+pub fn remote_code(exit: &Exit) -> ExitCode {
+    match exit {
+        Exit::Code(code) => u8::try_from(*code).unwrap_or(1).into(),
+        // the signal comes from the peer, and may be any value
+        Exit::Signal(signal) => signal
+            .checked_add(128)
+            .and_then(|code| u8::try_from(code).ok())
+            .unwrap_or(1)
+            .into(),
+        Exit::OtherError(_) => Failure::Remote.into(),
+    }
+}
+
+/// Installs the error report and panic hooks.
+///
+/// On panic, stdout is flushed and the process exits immediately, so that git doesn't keep waiting
+/// for the output of a helper whose other threads are still alive.
+pub fn install_hooks(program: &'static str) -> eyre::Result<()> {
+    let (panic_hook, eyre_hook) = HookBuilder::default().into_hooks();
+    eyre_hook.install()?;
+    let panic_hook = panic_hook.into_panic_hook();
+    panic::set_hook(Box::new(move |info| {
+        panic_hook(info);
+        let _ = io::stdout().flush();
+        let _ = writeln!(io::stderr(), "{program}: {}, aborting", Failure::Internal);
+        process::exit(Failure::Internal.code().into());
+    }));
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use std::{num::NonZeroU64, str::FromStr};
+
+/// Faults injected by the server, to test how clients cope with failures.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Faults {
+    /// Close every Nth connection without answering
+    pub reject_every: Option<NonZeroU64>,
+    /// Stop sending output once this many bytes are sent, leaving the session hanging
+    pub stall_after: Option<u64>,
+    /// Close the connection and kill the command once this many bytes of output are sent
+    pub abort_after: Option<u64>,
+}
+
+/// Fault hitting the output of a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFault {
+    Stall,
+    Abort,
+}
+
+impl FromStr for Faults {
+    type Err = String;
+
+    /// Parses comma-separated `reject-every=N`, `stall-after=BYTES` and `abort-after=BYTES`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut faults = Self::default();
+        for fault in s.split(',').filter(|fault| !fault.is_empty()) {
+            let (name, value) = fault
+                .split_once('=')
+                .ok_or_else(|| format!("expected FAULT=VALUE, got {fault:?}"))?;
+            let invalid = |e| format!("invalid value of {name}: {e}");
+            match name {
+                "reject-every" => faults.reject_every = Some(value.parse().map_err(invalid)?),
+                "stall-after" => faults.stall_after = Some(value.parse().map_err(invalid)?),
+                "abort-after" => faults.abort_after = Some(value.parse().map_err(invalid)?),
+                _ => {
+                    return Err(format!(
+                        "unknown fault {name:?}, expected `reject-every`, `stall-after` or `abort-after`"
+                    ))
+                }
+            }
+        }
+        Ok(faults)
+    }
+}
+
+impl Faults {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether the connection numbered `index`, counting from zero, is to be rejected.
+    pub fn rejects(&self, index: u64) -> bool {
+        self.reject_every
+            .is_some_and(|every| (index + 1).is_multiple_of(every.get()))
+    }
+
+    /// Fault hitting a session before it sends more output, once it has sent `sent` bytes.
+    pub fn output_fault(&self, sent: u64) -> Option<OutputFault> {
+        let reached = |limit: Option<u64>| limit.is_some_and(|limit| sent >= limit);
+        if reached(self.abort_after) {
+            Some(OutputFault::Abort)
+        } else if reached(self.stall_after) {
+            Some(OutputFault::Stall)
+        } else {
+            None
+        }
+    }
+}
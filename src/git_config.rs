@@ -0,0 +1,31 @@
+use std::{io, process::Stdio};
+
+use tokio::process;
+
+/// Reads a config value applicable to `url`, like `git config --get-urlmatch <name> <url>`.
+///
+/// `<section>.<url>.<key>` entries take precedence over plain `<section>.<key>` entries, following
+/// the same matching rules as `http.<url>.*` settings.
+pub async fn get_urlmatch(name: &str, url: &str) -> io::Result<Option<String>> {
+    let output = process::Command::new("git")
+        .args(["config", "--get-urlmatch", name, url])
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .await?;
+
+    // `git config` exits with 1 if the key is not set
+    if output.status.code() == Some(1) {
+        return Ok(None);
+    }
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "git config --get-urlmatch {name} {url} failed: {}",
+            output.status
+        )));
+    }
+
+    let value = String::from_utf8(output.stdout)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(Some(value.trim_end_matches('\n').to_owned()))
+}
@@ -0,0 +1,129 @@
+use std::{
+    env,
+    fs::File,
+    io::{self, Read as _, Write as _},
+    os::unix::prelude::{AsRawFd, CommandExt as _, FromRawFd, OwnedFd, RawFd},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+use tokio::{task, time};
+
+/// Variable listing the files passed to the new server, as `NAME=FD` separated by commas.
+pub const ENV: &str = "GRU_CREDENTIAL_HELPER_HANDOVER";
+
+/// Time the new server has to get ready before it is killed.
+pub const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Files handed over by the server being replaced, by name.
+///
+/// The same name can be given to several files, such as the locks of the sockets.
+#[derive(Debug, Default)]
+pub struct Handover {
+    files: Vec<(String, OwnedFd)>,
+}
+
+impl Handover {
+    /// Takes the files listed in [`ENV`], if this process replaces another server.
+    ///
+    /// Must be called before any thread is started, as it removes the variable.
+    pub fn from_env() -> io::Result<Option<Self>> {
+        let Some(list) = env::var_os(ENV) else {
+            return Ok(None);
+        };
+        env::remove_var(ENV);
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("invalid {ENV}"));
+
+        let mut files = vec![];
+        for entry in list.to_str().ok_or_else(invalid)?.split(',') {
+            let (name, fd) = entry.split_once('=').ok_or_else(invalid)?;
+            let fd = fd.parse::<RawFd>().map_err(|_| invalid())?;
+            // not passed further to the processes spawned by this one
+            if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            files.push((name.to_owned(), unsafe { OwnedFd::from_raw_fd(fd) }));
+        }
+        Ok(Some(Self { files }))
+    }
+
+    /// Takes the first file named `name`.
+    pub fn take(&mut self, name: &str) -> Option<OwnedFd> {
+        let index = self.files.iter().position(|(n, _)| n == name)?;
+        Some(self.files.remove(index).1)
+    }
+
+    /// Takes all the files named `name`.
+    pub fn take_all(&mut self, name: &str) -> Vec<OwnedFd> {
+        let mut taken = vec![];
+        while let Some(fd) = self.take(name) {
+            taken.push(fd);
+        }
+        taken
+    }
+
+    /// Tells the server being replaced that this one is ready, so that it stops accepting
+    /// connections.
+    pub fn ready(&mut self) -> io::Result<()> {
+        match self.take("ready") {
+            Some(fd) => File::from(fd).write_all(&[1]),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Starts a new server with the same program and arguments as this one, passing it `files`,
+/// and waits until it is ready.
+///
+/// The program is run from `argv[0]`, so that a binary replaced on disk is picked up. The new
+/// server is killed if it isn't ready within [`READY_TIMEOUT`]. Returns its pid.
+pub async fn spawn(files: &[(&str, RawFd)]) -> io::Result<u32> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (mut read, write) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    let mut passed = files.to_vec();
+    passed.push(("ready", write.as_raw_fd()));
+    let list = passed
+        .iter()
+        .map(|(name, fd)| format!("{name}={fd}"))
+        .collect::<Vec<_>>()
+        .join(",");
+    let mut args = env::args_os();
+    let program = args
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no program name"))?;
+
+    let mut command = Command::new(program);
+    command.args(args).env(ENV, list).stdin(Stdio::null());
+    let fds = passed.iter().map(|(_, fd)| *fd).collect::<Vec<_>>();
+    unsafe {
+        command.pre_exec(move || {
+            for &fd in &fds {
+                if libc::fcntl(fd, libc::F_SETFD, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    let mut child = command.spawn()?;
+    drop(write);
+
+    // ends with EOF if the new server exits, or is killed on timeout
+    let ready = task::spawn_blocking(move || read.read_exact(&mut [0]));
+    let res = match time::timeout(READY_TIMEOUT, ready).await {
+        Ok(Ok(Ok(()))) => return Ok(child.id()),
+        Ok(Ok(Err(_))) => Err(io::Error::other("new server exited before being ready")),
+        Ok(Err(e)) => Err(io::Error::other(e)),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "new server was not ready in time",
+        )),
+    };
+    let _ = child.kill();
+    let _ = task::spawn_blocking(move || child.wait()).await;
+    res
+}
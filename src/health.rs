@@ -0,0 +1,91 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use tokio::io::{AsyncBufReadExt as _, AsyncReadExt as _, AsyncWriteExt as _, BufReader};
+
+use crate::{
+    socket::{SocketListener, SocketStream},
+    task::TaskSet,
+};
+
+/// Server state reported by the health listener.
+#[derive(Debug, Default)]
+pub struct Health {
+    ready: AtomicBool,
+}
+
+impl Health {
+    /// Marks the server as ready (or not) to accept sessions.
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::Relaxed);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+}
+
+/// Answers health probes on `listener` forever.
+///
+/// Two protocols are understood, distinguished by the first line sent by the peer:
+///
+/// * HTTP `GET /healthz` and `GET /readyz`, answered with `200 OK` or `503 Service Unavailable`.
+/// * A bare `ping` line, answered with `ready` or `not ready`, for probes that don't speak HTTP.
+///
+/// `/healthz` succeeds as long as the server process runs; `/readyz` reflects [`Health::is_ready`].
+pub async fn serve(listener: SocketListener, health: Arc<Health>) {
+    // probes being answered, aborted when the listener is
+    let mut probes = TaskSet::default();
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            Some(_) = probes.join_next(), if !probes.is_empty() => continue,
+        };
+        match accepted {
+            Ok((stream, _addr)) => {
+                let health = Arc::clone(&health);
+                probes.spawn(async move {
+                    if let Err(e) = respond(stream, &health).await {
+                        tracing::debug!("failed to answer health probe: {e}");
+                    }
+                });
+            }
+            Err(e) => tracing::info!("failed to accept health probe: {e}"),
+        }
+    }
+}
+
+async fn respond(stream: SocketStream, health: &Health) -> io::Result<()> {
+    let (read_stream, mut write_stream) = stream.into_split();
+    // only the request line matters, so don't buffer anything longer
+    let mut read_stream = BufReader::new(read_stream.take(MAX_REQUEST_LINE));
+    let mut line = String::new();
+    read_stream.read_line(&mut line).await?;
+    let ready = health.is_ready();
+
+    let response = match line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["ping"] if ready => "ready\n".to_owned(),
+        ["ping"] => "not ready\n".to_owned(),
+        ["GET", "/healthz", _] => http_response("200 OK", "ok"),
+        ["GET", "/readyz", _] if ready => http_response("200 OK", "ready"),
+        ["GET", "/readyz", _] => http_response("503 Service Unavailable", "not ready"),
+        ["GET", _, _] => http_response("404 Not Found", "not found"),
+        _ => http_response("400 Bad Request", "bad request"),
+    };
+    write_stream.write_all(response.as_bytes()).await?;
+    write_stream.shutdown().await
+}
+
+const MAX_REQUEST_LINE: u64 = 1024;
+
+fn http_response(status: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.0 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}\n",
+        body.len() + 1
+    )
+}
@@ -0,0 +1,41 @@
+use std::io;
+
+/// Suggests how to fix a failure to connect to the server at `addr`.
+pub fn connect(err: &io::Error, addr: &str) -> Option<String> {
+    let hint = match err.kind() {
+        io::ErrorKind::ConnectionRefused => {
+            format!("is the server running and listening on {addr}?")
+        }
+        io::ErrorKind::NotFound if is_unix(addr) => {
+            format!(
+                "socket {addr} does not exist; is the server running, or is the socket forwarded?"
+            )
+        }
+        io::ErrorKind::PermissionDenied => {
+            format!("check the permissions of {addr} and its parent directories")
+        }
+        io::ErrorKind::TimedOut => {
+            "the server did not respond in time; check the network or increase --timeout".into()
+        }
+        _ => return None,
+    };
+    Some(format!(
+        "{hint} (run with --dry-run to see the resolved configuration)"
+    ))
+}
+
+/// Suggests how to fix a failure to bind a socket at `addr`.
+pub fn bind(err: &io::Error, addr: &str) -> Option<String> {
+    match err.kind() {
+        io::ErrorKind::AddrInUse if is_unix(addr) => Some(format!(
+            "another server may be running; if not, remove the stale socket file {addr}"
+        )),
+        io::ErrorKind::AddrInUse => Some(format!("another process is already listening on {addr}")),
+        io::ErrorKind::PermissionDenied => Some(format!("check the permissions to bind {addr}")),
+        _ => None,
+    }
+}
+
+fn is_unix(addr: &str) -> bool {
+    addr.starts_with("unix:") || addr.contains('/')
+}
@@ -0,0 +1,44 @@
+use std::io;
+
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncReadExt as _, AsyncWriteExt as _, BufReader},
+    net::TcpStream,
+};
+
+/// Largest status line read from an HTTP server.
+const MAX_STATUS_LINE: u64 = 1024;
+
+/// Sends `body` with an HTTP/1.1 `method` request to `path` on `authority` (`HOST:PORT`), over a
+/// new connection.
+///
+/// Fails unless the server answers with a `2xx` status; the rest of the response is ignored.
+pub async fn send(
+    authority: &str,
+    method: &str,
+    path: &str,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    let mut stream = TcpStream::connect(authority).await?;
+    let header = format!(
+        "{method} {path} HTTP/1.1\r\nHost: {authority}\r\nUser-Agent: gru-credential-helper-server/{}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        env!("CARGO_PKG_VERSION"),
+        body.len(),
+    );
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(body).await?;
+
+    let mut status_line = String::new();
+    BufReader::new(&mut stream)
+        .take(MAX_STATUS_LINE)
+        .read_line(&mut status_line)
+        .await?;
+    let status = status_line.split(' ').nth(1).unwrap_or_default();
+    if !status.starts_with('2') || status.len() != 3 {
+        return Err(io::Error::other(format!(
+            "{authority} answered {:?}",
+            status_line.trim_end()
+        )));
+    }
+    Ok(())
+}
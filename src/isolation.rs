@@ -0,0 +1,154 @@
+use std::{ffi::CString, io, os::unix::prelude::OsStrExt as _, path::Path, str::FromStr};
+
+use tokio::process::Command;
+
+/// Resource limits applied to each git process spawned by the server.
+#[derive(Debug, Clone, Default)]
+pub struct ChildLimits {
+    /// Scheduling niceness, added to the server's
+    pub nice: Option<libc::c_int>,
+    /// I/O scheduling class and level
+    pub io_priority: Option<IoPriority>,
+    /// `RLIMIT_AS`, in bytes
+    pub address_space: Option<u64>,
+    /// `RLIMIT_CPU`, in seconds
+    pub cpu_time: Option<u64>,
+    /// `cgroup.procs` file of the cgroup to move the process into
+    cgroup_procs: Option<CString>,
+}
+
+impl ChildLimits {
+    /// Moves spawned processes into the cgroup (v2) at `path`.
+    pub fn set_cgroup(&mut self, path: &Path) -> io::Result<()> {
+        let procs = path.join("cgroup.procs");
+        // fail early rather than in every child
+        std::fs::metadata(&procs)?;
+        self.cgroup_procs = Some(
+            CString::new(procs.as_os_str().as_bytes())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+        );
+        Ok(())
+    }
+
+    /// Arranges for the limits to be applied to the process spawned by `cmd`.
+    pub fn apply(&self, cmd: &mut Command) {
+        let limits = self.clone();
+        // Safety: only async-signal-safe functions are called and nothing is allocated.
+        unsafe {
+            cmd.pre_exec(move || limits.apply_to_current_process());
+        }
+    }
+
+    fn apply_to_current_process(&self) -> io::Result<()> {
+        if let Some(procs) = &self.cgroup_procs {
+            // writing 0 moves the writing process
+            let fd = unsafe { libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let written = unsafe { libc::write(fd, b"0".as_ptr().cast(), 1) };
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            if written < 0 {
+                return Err(err);
+            }
+        }
+        if let Some(nice) = self.nice {
+            // nice(2) can legitimately return -1, so errors are detected through errno
+            unsafe { *libc::__errno_location() = 0 };
+            if unsafe { libc::nice(nice) } == -1 {
+                let err = io::Error::last_os_error();
+                if err.raw_os_error() != Some(0) {
+                    return Err(err);
+                }
+            }
+        }
+        if let Some(io_priority) = self.io_priority {
+            const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_ioprio_set,
+                    IOPRIO_WHO_PROCESS,
+                    0,
+                    io_priority.value(),
+                )
+            };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if let Some(bytes) = self.address_space {
+            set_rlimit(libc::RLIMIT_AS, bytes)?;
+        }
+        if let Some(seconds) = self.cpu_time {
+            set_rlimit(libc::RLIMIT_CPU, seconds)?;
+        }
+        Ok(())
+    }
+}
+
+// glibc declares the resource argument with its own enum type, other libcs with an int
+#[cfg(target_env = "gnu")]
+type RlimitResource = libc::__rlimit_resource_t;
+#[cfg(not(target_env = "gnu"))]
+type RlimitResource = libc::c_int;
+
+fn set_rlimit(resource: RlimitResource, limit: u64) -> io::Result<()> {
+    let rlimit = libc::rlimit {
+        rlim_cur: limit,
+        rlim_max: limit,
+    };
+    if unsafe { libc::setrlimit(resource, &rlimit) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// I/O scheduling class, as set by `ionice(1)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriority {
+    Realtime(u8),
+    BestEffort(u8),
+    Idle,
+}
+
+impl IoPriority {
+    fn value(self) -> libc::c_int {
+        const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+        let (class, level) = match self {
+            Self::Realtime(level) => (1, level),
+            Self::BestEffort(level) => (2, level),
+            Self::Idle => (3, 0),
+        };
+        (class << IOPRIO_CLASS_SHIFT) | libc::c_int::from(level)
+    }
+}
+
+impl FromStr for IoPriority {
+    type Err = String;
+
+    /// Parses `idle`, `best-effort[:LEVEL]` or `realtime[:LEVEL]`, where `LEVEL` is 0 (highest)
+    /// to 7 (lowest) and defaults to 4.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (class, level) = match s.split_once(':') {
+            Some((class, level)) => (class, Some(level)),
+            None => (s, None),
+        };
+        let parse_level = || match level {
+            Some(level) => match level.parse::<u8>() {
+                Ok(level) if level <= 7 => Ok(level),
+                _ => Err(format!("invalid level {level:?}, expected 0 to 7")),
+            },
+            None => Ok(4),
+        };
+        match class {
+            "realtime" => Ok(Self::Realtime(parse_level()?)),
+            "best-effort" => Ok(Self::BestEffort(parse_level()?)),
+            "idle" if level.is_none() => Ok(Self::Idle),
+            "idle" => Err("the idle class has no level".into()),
+            _ => Err(format!(
+                "invalid class {class:?}, expected `realtime`, `best-effort` or `idle`"
+            )),
+        }
+    }
+}
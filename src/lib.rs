@@ -1,4 +1,36 @@
+pub mod admin;
+#[cfg(feature = "ssh-agent-auth")]
+pub mod agent;
+pub mod audit;
+#[cfg(feature = "ssh-agent-auth")]
+pub mod auth;
+pub mod budget;
+pub mod config;
+pub mod credential;
+pub mod daemon;
+pub mod dial;
+pub mod event;
+pub mod exit;
+pub mod fault;
+pub mod git_config;
+pub mod handover;
+pub mod health;
+pub mod hint;
+pub mod http;
+pub mod isolation;
+pub mod log;
+pub mod metrics;
+pub mod pidfile;
+pub mod privilege;
 pub mod protocol;
+pub mod sanitize;
+pub mod soak;
 pub mod socket;
+pub mod spawn;
+pub mod stdio;
 pub mod task;
+pub mod template;
 pub mod thread;
+pub mod trace;
+pub mod transport;
+pub mod version;
@@ -0,0 +1,204 @@
+use std::{ffi::CString, fmt, io, ptr};
+
+use tracing::{Level, Metadata};
+use tracing_subscriber::{
+    field::RecordFields,
+    filter::{self, ParseError},
+    fmt::{
+        format::{DefaultFields, Writer},
+        FormatFields, MakeWriter,
+    },
+    layer::{Layer as _, SubscriberExt as _},
+    util::SubscriberInitExt as _,
+    EnvFilter,
+};
+
+use crate::trace::ChromeTrace;
+
+/// Destination of log records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Sink {
+    /// Standard error
+    Stderr,
+    /// syslog(3), under the daemon facility with the level mapped to the priority
+    Syslog,
+    /// The systemd journal, with the fields of events and spans as journal fields
+    #[cfg(feature = "systemd")]
+    Journald,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum InitError {
+    #[error("invalid log filter")]
+    Filter(#[from] ParseError),
+    #[error("failed to connect to journald")]
+    Journald(#[source] io::Error),
+}
+
+/// Maps the number of `-v` flags to a log filter.
+///
+/// `0` shows errors only, `1` warnings, `2` informational messages, `3` debug messages and `4` or
+/// more everything.
+pub fn verbosity_filter(verbosity: u8) -> &'static str {
+    match verbosity {
+        0 => "error",
+        1 => "warn",
+        2 => "info",
+        3 => "debug",
+        _ => "trace",
+    }
+}
+
+/// Installs the global tracing subscriber, writing to stderr.
+///
+/// Falls back to `RUST_LOG`, and then to `default`, if `filter` is not given. Records never go
+/// to stdout, which carries the output of the credential helpers for git.
+pub fn init(filter: Option<&str>, default: &str) -> Result<(), ParseError> {
+    tracing_subscriber::fmt()
+        .with_writer(io::stderr)
+        .with_env_filter(env_filter(filter, default)?)
+        .init();
+    Ok(())
+}
+
+/// Installs the global tracing subscriber as [`init`] does, also recording the timings of all spans
+/// in `trace` whatever the filter.
+pub fn init_with_trace(
+    filter: Option<&str>,
+    default: &str,
+    trace: Option<&ChromeTrace>,
+) -> Result<(), ParseError> {
+    let trace = trace.map(|trace| {
+        trace
+            .clone()
+            .with_filter(filter::filter_fn(|meta| meta.is_span()))
+    });
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(io::stderr)
+                .with_filter(env_filter(filter, default)?),
+        )
+        .with(trace)
+        .init();
+    Ok(())
+}
+
+/// Installs the global tracing subscriber, writing each record to all of `sinks`.
+///
+/// The filter is chosen as in [`init`].
+pub fn init_sinks(filter: Option<&str>, default: &str, sinks: &[Sink]) -> Result<(), InitError> {
+    let filter = env_filter(filter, default)?;
+    let stderr = sinks
+        .contains(&Sink::Stderr)
+        .then(|| tracing_subscriber::fmt::layer().with_writer(io::stderr));
+    let syslog = sinks.contains(&Sink::Syslog).then(|| {
+        // a null identity makes syslog use the program name
+        unsafe { libc::openlog(ptr::null(), libc::LOG_PID, libc::LOG_DAEMON) };
+        // the timestamp and the level are part of the syslog record
+        tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .without_time()
+            .with_level(false)
+            .fmt_fields(PlainFields::default())
+            .with_writer(Syslog)
+    });
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(stderr)
+        .with(syslog);
+    #[cfg(feature = "systemd")]
+    let registry = registry.with(
+        sinks
+            .contains(&Sink::Journald)
+            .then(tracing_journald::layer)
+            .transpose()
+            .map_err(InitError::Journald)?,
+    );
+    registry.init();
+    Ok(())
+}
+
+fn env_filter(filter: Option<&str>, default: &str) -> Result<EnvFilter, ParseError> {
+    match filter {
+        Some(filter) => EnvFilter::try_new(filter),
+        None => EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new(default)),
+    }
+}
+
+/// Field formatter of the syslog sink.
+///
+/// Formatted span fields are cached in the span by the type of the formatter, so a type distinct
+/// from the stderr sink's keeps the colors of stderr out of syslog.
+#[derive(Debug, Default)]
+struct PlainFields(DefaultFields);
+
+impl<'writer> FormatFields<'writer> for PlainFields {
+    fn format_fields<R: RecordFields>(&self, writer: Writer<'writer>, fields: R) -> fmt::Result {
+        self.0.format_fields(writer, fields)
+    }
+}
+
+/// Makes writers sending each record to syslog(3).
+#[derive(Debug, Clone, Copy)]
+struct Syslog;
+
+impl<'a> MakeWriter<'a> for Syslog {
+    type Writer = SyslogRecord;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        SyslogRecord::new(libc::LOG_INFO)
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        let priority = match *meta.level() {
+            Level::ERROR => libc::LOG_ERR,
+            Level::WARN => libc::LOG_WARNING,
+            Level::INFO => libc::LOG_INFO,
+            Level::DEBUG | Level::TRACE => libc::LOG_DEBUG,
+        };
+        SyslogRecord::new(priority)
+    }
+}
+
+/// A single record, sent to syslog when dropped.
+#[derive(Debug)]
+struct SyslogRecord {
+    priority: libc::c_int,
+    buf: Vec<u8>,
+}
+
+impl SyslogRecord {
+    fn new(priority: libc::c_int) -> Self {
+        Self {
+            priority,
+            buf: Vec::new(),
+        }
+    }
+}
+
+impl io::Write for SyslogRecord {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SyslogRecord {
+    fn drop(&mut self) {
+        let mut buf = std::mem::take(&mut self.buf);
+        if buf.last() == Some(&b'\n') {
+            buf.pop();
+        }
+        if buf.is_empty() {
+            return;
+        }
+        buf.retain(|&b| b != 0);
+        let msg = CString::new(buf).expect("NUL bytes were removed");
+        unsafe { libc::syslog(self.priority, c"%s".as_ptr(), msg.as_ptr()) };
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs, io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use async_trait::async_trait;
+use tokio::net::{self, UdpSocket};
+
+use crate::{event, http};
+
+/// Counters and gauges updated by the server.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Connections accepted
+    pub accepted: Counter,
+    /// Sessions that spawned git
+    pub spawned: Counter,
+    /// Sessions that failed before or while spawning git
+    pub failed: Counter,
+    /// Sessions that had to wait for the memory budget
+    pub budget_waits: Counter,
+    /// Keys rejected during authentication
+    pub auth_failures: Counter,
+    /// Sessions currently running
+    pub active: Gauge,
+    /// Tasks of sessions currently running, see [`Metrics::track_task`]
+    pub tasks: Gauge,
+    /// File descriptors open in the server, sampled
+    pub open_fds: Gauge,
+    /// Bytes of the memory budget held by sessions, sampled
+    pub budget_used: Gauge,
+}
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        let previous = self.0.fetch_sub(1, Ordering::Relaxed);
+        debug_assert!(previous > 0, "gauge decremented below zero");
+    }
+
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Values of all metrics at a point in time.
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    pub counters: Vec<(&'static str, u64)>,
+    pub gauges: Vec<(&'static str, i64)>,
+}
+
+impl Metrics {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            counters: vec![
+                ("sessions.accepted", self.accepted.get()),
+                ("sessions.spawned", self.spawned.get()),
+                ("sessions.failed", self.failed.get()),
+                ("sessions.budget_waits", self.budget_waits.get()),
+                ("sessions.auth_failures", self.auth_failures.get()),
+            ],
+            gauges: vec![
+                ("sessions.active", self.active.get()),
+                ("sessions.tasks", self.tasks.get()),
+                ("process.open_fds", self.open_fds.get()),
+                ("budget.used_bytes", self.budget_used.get()),
+            ],
+        }
+    }
+
+    /// Counts a task of a session in [`tasks`](Self::tasks) until the returned guard is dropped.
+    pub fn track_task(self: &Arc<Self>) -> TaskGuard {
+        self.tasks.inc();
+        TaskGuard {
+            metrics: Arc::clone(self),
+        }
+    }
+}
+
+/// Task counted in [`Metrics::tasks`], see [`Metrics::track_task`].
+#[derive(Debug)]
+pub struct TaskGuard {
+    metrics: Arc<Metrics>,
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        self.metrics.tasks.dec();
+    }
+}
+
+/// Counts the file descriptors open in this process.
+pub fn open_fds() -> io::Result<i64> {
+    // the directory being read holds a descriptor too
+    Ok(fs::read_dir("/proc/self/fd")?.count() as i64 - 1)
+}
+
+/// Resources still held while no session is running, which outlived their sessions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Leaks {
+    pub active: i64,
+    pub tasks: i64,
+    pub budget_bytes: i64,
+    /// Descriptors open beyond those open before serving
+    pub fds: i64,
+}
+
+impl Leaks {
+    /// Compares the metrics with their idle values, `baseline_fds` being the descriptors open
+    /// before serving.
+    pub fn find(metrics: &Metrics, baseline_fds: i64) -> Self {
+        Self {
+            active: metrics.active.get(),
+            tasks: metrics.tasks.get(),
+            budget_bytes: metrics.budget_used.get(),
+            fds: (metrics.open_fds.get() - baseline_fds).max(0),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Destination the server pushes its metrics to.
+#[async_trait]
+pub trait Sink: Send {
+    async fn send(&mut self, snapshot: &Snapshot) -> io::Result<()>;
+}
+
+/// Sink sending metrics to a statsd daemon over UDP.
+///
+/// Counters are sent as the increase since the previous flush, as statsd expects.
+#[derive(Debug)]
+pub struct Statsd {
+    socket: UdpSocket,
+    prefix: String,
+    sent: HashMap<&'static str, u64>,
+}
+
+impl Statsd {
+    pub async fn connect(addr: &str, prefix: impl Into<String>) -> io::Result<Self> {
+        let addr = net::lookup_host(addr).await?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "address resolved to nothing")
+        })?;
+        let local_addr = match addr {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let socket = UdpSocket::bind(local_addr).await?;
+        socket.connect(addr).await?;
+        Ok(Self {
+            socket,
+            prefix: prefix.into(),
+            sent: HashMap::new(),
+        })
+    }
+}
+
+#[async_trait]
+impl Sink for Statsd {
+    async fn send(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        let mut packet = String::new();
+        for &(name, value) in &snapshot.counters {
+            let delta = value - self.sent.get(name).copied().unwrap_or(0);
+            if delta > 0 {
+                let _ = writeln!(packet, "{}.{name}:{delta}|c", self.prefix);
+            }
+        }
+        for &(name, value) in &snapshot.gauges {
+            let _ = writeln!(packet, "{}.{name}:{value}|g", self.prefix);
+        }
+        self.socket.send(packet.as_bytes()).await?;
+        // increments that failed to be sent are sent with the next flush
+        self.sent.extend(snapshot.counters.iter().copied());
+        Ok(())
+    }
+}
+
+/// Sink pushing metrics to a Prometheus Pushgateway over HTTP.
+///
+/// The metrics replace the group of the job named after the prefix at each flush. Counters are
+/// sent as their total, as Prometheus expects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushGateway {
+    authority: String,
+    base_path: String,
+    prefix: String,
+}
+
+impl FromStr for PushGateway {
+    type Err = String;
+
+    /// Parses the URL of the Pushgateway, `http://HOST[:PORT][/PATH]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = s
+            .strip_prefix("http://")
+            .ok_or("expected http://HOST[:PORT][/PATH]")?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+        if authority.is_empty() {
+            return Err(format!("no host in {s:?}"));
+        }
+        Ok(Self {
+            authority: event::with_default_port(authority, 9091),
+            base_path: path.trim_end_matches('/').to_owned(),
+            prefix: "gru".into(),
+        })
+    }
+}
+
+impl PushGateway {
+    /// Sets the prefix of the metric names, also used as the job name.
+    pub fn prefix(self, prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            ..self
+        }
+    }
+
+    /// Address (`HOST:PORT`) connected to.
+    pub fn authority(&self) -> &str {
+        &self.authority
+    }
+
+    /// Metrics in the Prometheus text format.
+    pub fn format(&self, snapshot: &Snapshot) -> String {
+        let counters = snapshot
+            .counters
+            .iter()
+            .map(|&(name, value)| (name, "counter", value.to_string()));
+        let gauges = snapshot
+            .gauges
+            .iter()
+            .map(|&(name, value)| (name, "gauge", value.to_string()));
+        let mut text = String::new();
+        for (name, kind, value) in counters.chain(gauges) {
+            let name = format!("{}_{name}", self.prefix).replace(['.', '-'], "_");
+            let _ = writeln!(text, "# TYPE {name} {kind}\n{name} {value}");
+        }
+        text
+    }
+}
+
+#[async_trait]
+impl Sink for PushGateway {
+    async fn send(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        let path = format!("{}/metrics/job/{}", self.base_path, self.prefix);
+        let body = self.format(snapshot);
+        http::send(
+            &self.authority,
+            "PUT",
+            &path,
+            "text/plain; version=0.0.4",
+            body.as_bytes(),
+        )
+        .await
+    }
+}
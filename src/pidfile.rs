@@ -0,0 +1,99 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read as _, Seek as _, Write as _},
+    os::unix::prelude::{AsRawFd, OwnedFd, RawFd},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum PidFileError {
+    #[error("failed to lock pidfile")]
+    Io(#[from] io::Error),
+    #[error("another instance is already running{}", pid.map(|pid| format!(" (pid {pid})")).unwrap_or_default())]
+    Running { pid: Option<u32> },
+}
+
+/// File holding the pid of the running server, locked until it exits.
+///
+/// The lock is an `flock`, released however the process exits, so a pidfile left behind by a
+/// crashed server is not locked and is taken over. The file is removed when dropped, unless it
+/// was handed over to another server.
+#[derive(Debug)]
+pub struct PidFile {
+    path: Option<PathBuf>,
+    file: File,
+}
+
+impl PidFile {
+    /// Locks the pidfile at `path` and writes the pid of this process to it.
+    ///
+    /// Fails with [`PidFileError::Running`] if another process holds the lock.
+    pub fn create(path: &Path) -> Result<Self, PidFileError> {
+        let mut file = File::options()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(path)?;
+        if !try_lock(&file)? {
+            let mut pid = String::new();
+            let _ = file.read_to_string(&mut pid);
+            return Err(PidFileError::Running {
+                pid: pid.trim().parse().ok(),
+            });
+        }
+        Self::write(path, file).map_err(Into::into)
+    }
+
+    /// Takes over the pidfile at `path`, already locked through `fd` by the server this one
+    /// replaces.
+    pub fn adopt(path: &Path, fd: OwnedFd) -> io::Result<Self> {
+        Self::write(path, fd.into())
+    }
+
+    fn write(path: &Path, mut file: File) -> io::Result<Self> {
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(Self {
+            path: Some(path.to_owned()),
+            file,
+        })
+    }
+
+    /// Closes the pidfile without removing it, as the server it was handed over to holds it.
+    pub fn handed_over(mut self) {
+        self.path = None;
+    }
+}
+
+impl AsRawFd for PidFile {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // removed while still locked, so that no other instance is using it
+        if let Some(path) = &self.path {
+            if let Err(e) = fs::remove_file(path) {
+                tracing::warn!("failed to remove pidfile {}: {e}", path.display());
+            }
+        }
+    }
+}
+
+/// Takes an exclusive `flock` on `file` without waiting.
+///
+/// Returns `false` if another open file holds the lock.
+pub(crate) fn try_lock(file: &File) -> io::Result<bool> {
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } < 0 {
+        let err = io::Error::last_os_error();
+        if err.kind() == io::ErrorKind::WouldBlock {
+            return Ok(false);
+        }
+        return Err(err);
+    }
+    Ok(true)
+}
@@ -0,0 +1,136 @@
+use std::{
+    env,
+    ffi::{CStr, CString, OsStr},
+    io, mem,
+    os::unix::prelude::OsStrExt as _,
+    path::PathBuf,
+    ptr,
+};
+
+/// Account the server switches to after binding its sockets.
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+    pub name: CString,
+    pub home: Option<PathBuf>,
+}
+
+impl Account {
+    /// Looks up a user by name or numeric id.
+    pub fn lookup_user(user: &str) -> io::Result<Self> {
+        let name = CString::new(user).map_err(invalid_input)?;
+        let mut buf = vec![0; 1024];
+        loop {
+            let mut passwd = unsafe { mem::zeroed::<libc::passwd>() };
+            let mut result = ptr::null_mut();
+            let ret = match user.parse::<libc::uid_t>() {
+                Ok(uid) => unsafe {
+                    libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result)
+                },
+                Err(_) => unsafe {
+                    libc::getpwnam_r(
+                        name.as_ptr(),
+                        &mut passwd,
+                        buf.as_mut_ptr(),
+                        buf.len(),
+                        &mut result,
+                    )
+                },
+            };
+            match ret {
+                0 if result.is_null() => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("no such user: {user}"),
+                    ))
+                }
+                0 => unsafe {
+                    let home = CStr::from_ptr(passwd.pw_dir).to_bytes();
+                    return Ok(Self {
+                        uid: passwd.pw_uid,
+                        gid: passwd.pw_gid,
+                        name: CStr::from_ptr(passwd.pw_name).to_owned(),
+                        home: (!home.is_empty()).then(|| OsStr::from_bytes(home).into()),
+                    });
+                },
+                libc::ERANGE => buf.resize(buf.len() * 2, 0),
+                errno => return Err(io::Error::from_raw_os_error(errno)),
+            }
+        }
+    }
+}
+
+/// Looks up a group by name or numeric id.
+pub fn lookup_group(group: &str) -> io::Result<libc::gid_t> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let name = CString::new(group).map_err(invalid_input)?;
+    let mut buf = vec![0; 1024];
+    loop {
+        let mut grp = unsafe { mem::zeroed::<libc::group>() };
+        let mut result = ptr::null_mut();
+        let ret = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut grp,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        match ret {
+            0 if result.is_null() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no such group: {group}"),
+                ))
+            }
+            0 => return Ok(grp.gr_gid),
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            errno => return Err(io::Error::from_raw_os_error(errno)),
+        }
+    }
+}
+
+/// Switches the process to `user` and `group`.
+///
+/// Without a user, only the group is changed. Without a group, the user's primary group is used.
+/// Supplementary groups are replaced by the user's groups, or cleared when only a group is given.
+/// `HOME` and `USER` are updated so that git finds the user's configuration and credentials.
+pub fn drop_privileges(user: Option<&Account>, group: Option<libc::gid_t>) -> io::Result<()> {
+    let gid = group.or(user.map(|user| user.gid));
+    if let Some(gid) = gid {
+        match user {
+            Some(user) => check(unsafe { libc::initgroups(user.name.as_ptr(), gid) })?,
+            None => check(unsafe { libc::setgroups(0, ptr::null()) })?,
+        }
+        check(unsafe { libc::setgid(gid) })?;
+    }
+    if let Some(user) = user {
+        check(unsafe { libc::setuid(user.uid) })?;
+        // make sure root can't be regained
+        if user.uid != 0 && unsafe { libc::setuid(0) } == 0 {
+            return Err(io::Error::other(
+                "privileges could be regained after setuid",
+            ));
+        }
+        if let Some(home) = &user.home {
+            env::set_var("HOME", home);
+        }
+        env::set_var("USER", OsStr::from_bytes(user.name.as_bytes()));
+    }
+    Ok(())
+}
+
+fn check(ret: libc::c_int) -> io::Result<()> {
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn invalid_input(e: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, e)
+}
@@ -1,8 +1,10 @@
-use std::sync::Arc;
+use std::{io, marker::PhantomData, pin::Pin, sync::Arc};
 
-use bytes::BytesMut;
+use bytes::{BufMut as _, Bytes, BytesMut};
 use serde::{Deserialize, Serialize};
-use tokio_serde::{formats::MessagePack, Framed};
+use tokio_serde::Framed;
+use tokio_util::codec::LengthDelimitedCodec;
+use ulid::Ulid;
 
 pub type Sender<Transport, SinkItem> = Framed<Transport, (), SinkItem, MessagePack<(), SinkItem>>;
 pub fn new_sender<Transport, SinkItem>(stream: Transport) -> Sender<Transport, SinkItem> {
@@ -14,7 +16,265 @@ pub fn new_receiver<Transport, Item>(stream: Transport) -> Receiver<Transport, I
     Receiver::new(stream, MessagePack::default())
 }
 
-#[derive(Debug, Clone, Copy, clap::Subcommand, Serialize, Deserialize)]
+/// MessagePack encoding of the messages, compatible with `tokio_serde::formats::MessagePack`.
+///
+/// Frames are decoded in place rather than through a reader, so the lengths of strings and
+/// binaries are checked against the frame before anything is allocated: a peer can't make the
+/// decoder allocate more than the frame by claiming a huge length.
+#[derive(Debug)]
+pub struct MessagePack<Item, SinkItem> {
+    ghost: PhantomData<fn() -> (Item, SinkItem)>,
+}
+
+impl<Item, SinkItem> Default for MessagePack<Item, SinkItem> {
+    fn default() -> Self {
+        Self { ghost: PhantomData }
+    }
+}
+
+impl<Item, SinkItem> tokio_serde::Deserializer<Item> for MessagePack<Item, SinkItem>
+where
+    Item: for<'de> Deserialize<'de>,
+{
+    type Error = io::Error;
+
+    fn deserialize(self: Pin<&mut Self>, src: &BytesMut) -> io::Result<Item> {
+        decode_slice(src)
+    }
+}
+
+impl<Item, SinkItem> tokio_serde::Serializer<SinkItem> for MessagePack<Item, SinkItem>
+where
+    SinkItem: Serialize,
+{
+    type Error = io::Error;
+
+    fn serialize(self: Pin<&mut Self>, item: &SinkItem) -> io::Result<Bytes> {
+        encode(item).map(Bytes::from)
+    }
+}
+
+/// Prefix of handshake frames.
+///
+/// A MessagePack-encoded [`SpawnMessage`] starts with an array marker, so a server can tell a
+/// handshake from the first frame of a client that doesn't send one.
+pub const HELLO_MAGIC: &[u8; 4] = b"GRU\0";
+
+/// Largest frame accepted before the request of the client is received.
+///
+/// Handshake, session id, authentication and request frames are all far smaller. Until a
+/// connection is authenticated, it can only make the server buffer a few frames of this size,
+/// as their number is bounded by the protocol.
+pub const MAX_HANDSHAKE_FRAME_LENGTH: usize = 64 * 1024;
+
+/// Largest frame accepted once the session has started.
+///
+/// These frames carry a chunk of the standard I/O of the command, of at most the buffer size
+/// of the sender.
+pub const MAX_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+
+/// Largest buffer size whose chunks, once wrapped in a message, still fit in a frame.
+///
+/// The envelope of a chunk of stdio takes a few dozen bytes; a whole KiB is kept for it.
+pub const MAX_BUFFER_SIZE: usize = MAX_FRAME_LENGTH - 1024;
+
+/// Codec splitting a stream into frames of at most `max_frame_length` bytes.
+///
+/// Longer frames are rejected from their length prefix, before their payload is buffered.
+pub fn codec(max_frame_length: usize) -> LengthDelimitedCodec {
+    LengthDelimitedCodec::builder()
+        .max_frame_length(max_frame_length)
+        .new_codec()
+}
+
+/// Newest version of the handshake and of the messages that follow it.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest protocol version still understood.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Optional features understood by this build.
+///
+/// A feature is used on a connection only if both ends list it in their handshake, so new
+/// features can be added without breaking peers that don't know them.
+#[cfg(feature = "ssh-agent-auth")]
+pub const FEATURES: &[&str] = &[PRIORITY_FEATURE, SESSION_ID_FEATURE, AUTH_FEATURE];
+#[cfg(not(feature = "ssh-agent-auth"))]
+pub const FEATURES: &[&str] = &[PRIORITY_FEATURE, SESSION_ID_FEATURE];
+
+/// Feature letting the client choose the [`Priority`] of its session.
+///
+/// When it is negotiated, the client sends the priority in [`SpawnMessage`]. Otherwise the
+/// session is interactive.
+pub const PRIORITY_FEATURE: &str = "priority";
+
+/// Feature letting the client tell the server the id of the session, so the logs of both ends
+/// can be correlated.
+///
+/// When it is negotiated, the client sends [`SessionHello`] right after the server's handshake.
+pub const SESSION_ID_FEATURE: &str = "session-id";
+
+/// Feature enabled by servers requiring clients to authenticate with an SSH key.
+///
+/// When it is negotiated, the server sends [`AuthChallenge`] after its handshake and the client
+/// answers with [`AuthResponse`] until the server accepts one of them.
+pub const AUTH_FEATURE: &str = "ssh-agent-auth";
+
+/// Handshake sent by the client before [`SpawnMessage`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientHello {
+    /// Newest protocol version supported by the client
+    pub protocol_version: u32,
+    /// Version of the client package
+    pub client_version: String,
+    /// Command the client is going to request
+    pub command: Command,
+    /// URL of the remote the credential is for, if known
+    pub url: Option<String>,
+    /// Optional features supported by the client
+    pub features: Vec<String>,
+}
+
+impl ClientHello {
+    pub fn new(command: Command, url: Option<String>) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            client_version: env!("CARGO_PKG_VERSION").into(),
+            command,
+            url,
+            features: FEATURES.iter().map(|&f| f.into()).collect(),
+        }
+    }
+
+    /// Chooses the protocol version and features used on the connection.
+    ///
+    /// The newest version supported by both ends is used. The client is rejected if it only
+    /// supports versions older than [`MIN_PROTOCOL_VERSION`].
+    pub fn negotiate(&self) -> Result<ServerHello, String> {
+        self.negotiate_with(FEATURES)
+    }
+
+    /// Like [`negotiate`](Self::negotiate), enabling only the features in `features`.
+    pub fn negotiate_with(&self, features: &[&str]) -> Result<ServerHello, String> {
+        if self.protocol_version < MIN_PROTOCOL_VERSION {
+            return Err(format!(
+                "protocol version {} is no longer supported (server supports {}..={})",
+                self.protocol_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+            ));
+        }
+        Ok(ServerHello {
+            protocol_version: self.protocol_version.min(PROTOCOL_VERSION),
+            server_version: env!("CARGO_PKG_VERSION").into(),
+            features: self
+                .features
+                .iter()
+                .filter(|f| features.contains(&f.as_str()))
+                .cloned()
+                .collect(),
+        })
+    }
+}
+
+/// Identifier of the session chosen by the client, see [`SESSION_ID_FEATURE`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionHello {
+    pub session_id: Ulid,
+}
+
+/// Reply to [`ClientHello`], or the reason the client was rejected.
+pub type HelloReply = Result<ServerHello, String>;
+
+/// Parameters of the connection chosen by the server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerHello {
+    /// Protocol version used on the connection
+    pub protocol_version: u32,
+    /// Version of the server package
+    pub server_version: String,
+    /// Optional features enabled on the connection
+    pub features: Vec<String>,
+}
+
+impl ServerHello {
+    /// Checks that the server chose parameters the client supports.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&self.protocol_version) {
+            return Err(format!(
+                "server chose unsupported protocol version {}",
+                self.protocol_version
+            ));
+        }
+        if let Some(feature) = self
+            .features
+            .iter()
+            .find(|f| !FEATURES.contains(&f.as_str()))
+        {
+            return Err(format!("server enabled unknown feature {feature:?}"));
+        }
+        Ok(())
+    }
+
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
+/// Random data to be signed by the client, sent after [`ServerHello`] when [`AUTH_FEATURE`] is
+/// enabled.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthChallenge {
+    pub challenge: Bytes,
+}
+
+/// Signature of the challenge, in the SSH wire encoding.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthResponse {
+    pub public_key: Bytes,
+    pub signature: Bytes,
+}
+
+/// Reply to [`AuthResponse`], or the reason the key was rejected.
+pub type AuthReply = Result<(), String>;
+
+/// Encodes a handshake message into a frame.
+pub fn encode_hello<T: Serialize>(msg: &T) -> io::Result<Bytes> {
+    let payload = encode(msg)?;
+    let mut frame = BytesMut::with_capacity(HELLO_MAGIC.len() + payload.len());
+    frame.put_slice(HELLO_MAGIC);
+    frame.put_slice(&payload);
+    Ok(frame.freeze())
+}
+
+/// Decodes a handshake frame, or returns `None` if `frame` is not one.
+pub fn decode_hello<T>(frame: &BytesMut) -> Option<io::Result<T>>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    frame
+        .starts_with(HELLO_MAGIC)
+        .then(|| decode_slice(&frame[HELLO_MAGIC.len()..]))
+}
+
+/// Decodes a frame containing a message of the main protocol.
+pub fn decode<T>(frame: &BytesMut) -> io::Result<T>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    decode_slice(frame)
+}
+
+fn encode<T: Serialize>(msg: &T) -> io::Result<Vec<u8>> {
+    rmp_serde::to_vec(msg).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn decode_slice<T>(payload: &[u8]) -> io::Result<T>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    rmp_serde::from_read_ref(payload).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::Subcommand, Serialize, Deserialize)]
 pub enum Command {
     /// Returns a matching credential from remote server, if any exists
     Get,
@@ -24,9 +284,33 @@ pub enum Command {
     Erase,
 }
 
+impl Command {
+    /// Arguments of the `git` command the server runs for this command.
+    pub fn git_args(&self) -> [&'static str; 2] {
+        match self {
+            Self::Get => ["credential", "fill"],
+            Self::Store => ["credential", "approve"],
+            Self::Erase => ["credential", "reject"],
+        }
+    }
+}
+
+/// Scheduling class of a session, used by the server to share its memory budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, Serialize, Deserialize)]
+pub enum Priority {
+    /// A user is waiting for the result
+    #[default]
+    Interactive,
+    /// Unattended jobs such as CI or mirroring
+    Batch,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SpawnMessage {
     pub command: Command,
+    /// Sent only when [`PRIORITY_FEATURE`] is negotiated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<Priority>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
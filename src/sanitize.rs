@@ -0,0 +1,97 @@
+/// Filter removing terminal control sequences from text sent by the server.
+///
+/// Escape sequences (CSI, OSC and the other string sequences), C0 control characters other than
+/// newline, carriage return and tab, DEL and UTF-8 encoded C1 control characters are removed, so
+/// that a compromised server can't move the cursor, retitle the window or rewrite the terminal's
+/// clipboard through the messages shown to the user. Sequences may be split across chunks.
+#[derive(Debug, Clone, Default)]
+pub struct Sanitizer {
+    state: State,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum State {
+    #[default]
+    Ground,
+    /// After ESC
+    Escape,
+    /// In a control sequence (`ESC [`), until its final byte
+    Csi,
+    /// In a string sequence (OSC, DCS, SOS, PM or APC), until BEL or `ESC \`
+    String { after_escape: bool },
+    /// After the first byte of a UTF-8 encoded C1 control character, or of another character
+    C1Lead,
+}
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+
+impl Sanitizer {
+    /// Appends `input` to `output` without its control sequences.
+    pub fn filter(&mut self, input: &[u8], output: &mut Vec<u8>) {
+        for &byte in input {
+            self.state = self.next(byte, output);
+        }
+    }
+
+    fn next(&self, byte: u8, output: &mut Vec<u8>) -> State {
+        match self.state {
+            State::Ground => match byte {
+                ESC => State::Escape,
+                b'\n' | b'\r' | b'\t' => {
+                    output.push(byte);
+                    State::Ground
+                }
+                0x00..=0x1f | 0x7f => State::Ground,
+                0xc2 => State::C1Lead,
+                _ => {
+                    output.push(byte);
+                    State::Ground
+                }
+            },
+            State::C1Lead => match byte {
+                // 8-bit CSI, and the string sequences
+                0x9b => State::Csi,
+                0x90 | 0x98 | 0x9d | 0x9e | 0x9f => State::String {
+                    after_escape: false,
+                },
+                0x80..=0x9f => State::Ground,
+                _ => {
+                    output.push(0xc2);
+                    Self::default().next(byte, output)
+                }
+            },
+            State::Escape => match byte {
+                b'[' => State::Csi,
+                b']' | b'P' | b'X' | b'^' | b'_' => State::String {
+                    after_escape: false,
+                },
+                // intermediate bytes, followed by the final byte
+                0x20..=0x2f => State::Escape,
+                ESC => State::Escape,
+                _ => State::Ground,
+            },
+            State::Csi => match byte {
+                0x40..=0x7e => State::Ground,
+                ESC => State::Escape,
+                _ => State::Csi,
+            },
+            State::String { after_escape } => match byte {
+                BEL => State::Ground,
+                b'\\' if after_escape => State::Ground,
+                ESC => State::String { after_escape: true },
+                _ => State::String {
+                    after_escape: false,
+                },
+            },
+        }
+    }
+}
+
+/// Removes terminal control sequences from a complete message, see [`Sanitizer`].
+pub fn text(message: &str) -> String {
+    let mut output = Vec::with_capacity(message.len());
+    Sanitizer::default().filter(message.as_bytes(), &mut output);
+    // only whole characters and ASCII bytes are removed
+    String::from_utf8_lossy(&output).into_owned()
+}
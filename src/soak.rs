@@ -0,0 +1,235 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::PathBuf,
+    process::Stdio,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tokio::{io::AsyncWriteExt as _, process, time};
+
+use crate::task::TaskSet;
+
+/// Interval between samples of the server's resource usage.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Settings of a soak test.
+#[derive(Debug, Clone)]
+pub struct SoakConfig {
+    /// Path of `gru-credential-helper-client`
+    pub client: PathBuf,
+    /// Server address, passed to `--connect`
+    pub connect_addr: String,
+    /// Credential operation run by each session (`get`, `store` or `erase`)
+    pub operation: String,
+    /// Credential description written to each client
+    pub input: Vec<u8>,
+    /// Number of sessions kept running at once
+    pub clients: usize,
+    pub duration: Duration,
+    /// Time after which a session's client is killed and the session counted as failed
+    pub session_timeout: Duration,
+    /// Server process whose memory and file descriptors are sampled
+    pub server_pid: Option<u32>,
+}
+
+/// Outcome of a soak test.
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub sessions: u64,
+    pub failures: u64,
+    /// Failed sessions, as a fraction of all sessions
+    pub error_rate: f64,
+    pub sessions_per_second: f64,
+    /// Latency of all sessions, from spawning the client to its exit
+    pub latency_ms: Percentiles,
+    /// Number of failed sessions by exit status of the client, or `timed out`
+    pub failures_by_exit: BTreeMap<String, u64>,
+    pub server: Option<ServerUsage>,
+}
+
+/// Percentiles of a set of durations, in milliseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Percentiles {
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Percentiles {
+    /// Computes the percentiles of `durations` by the nearest-rank method.
+    pub fn of(durations: &mut [Duration]) -> Self {
+        durations.sort_unstable();
+        let rank = |percent: usize| {
+            if durations.is_empty() {
+                return 0.0;
+            }
+            let index = (durations.len() * percent).div_ceil(100).max(1) - 1;
+            durations[index].as_secs_f64() * 1e3
+        };
+        Self {
+            p50: rank(50),
+            p90: rank(90),
+            p99: rank(99),
+            max: rank(100),
+        }
+    }
+}
+
+/// Resource usage of the server, read from `/proc`.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ServerUsage {
+    pub peak_rss_bytes: u64,
+    pub peak_fds: u64,
+    /// Usage at the end of the test, which should come back to the idle level
+    pub final_rss_bytes: u64,
+    pub final_fds: u64,
+}
+
+impl ServerUsage {
+    /// Reads the resident memory and the number of open files of process `pid`.
+    pub fn sample(pid: u32) -> io::Result<(u64, u64)> {
+        let status = fs::read_to_string(format!("/proc/{pid}/status"))?;
+        let rss_kib = status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmRSS:"))
+            .and_then(|value| {
+                value
+                    .trim()
+                    .trim_end_matches("kB")
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no VmRSS in status"))?;
+        let fds = fs::read_dir(format!("/proc/{pid}/fd"))?.count() as u64;
+        Ok((rss_kib * 1024, fds))
+    }
+
+    fn add_sample(&mut self, (rss, fds): (u64, u64)) {
+        self.peak_rss_bytes = self.peak_rss_bytes.max(rss);
+        self.peak_fds = self.peak_fds.max(fds);
+        self.final_rss_bytes = rss;
+        self.final_fds = fds;
+    }
+}
+
+/// Result of a single session.
+#[derive(Debug)]
+struct Outcome {
+    latency: Duration,
+    /// Exit status of the client if it failed
+    failure: Option<String>,
+}
+
+/// Runs sessions with `config.clients` clients at once until `config.duration` has passed.
+///
+/// Sessions running at the deadline are completed and counted. The clients still running are
+/// killed if this returns early, e.g. because one couldn't be spawned.
+pub async fn run(config: &SoakConfig) -> io::Result<Report> {
+    if let Some(pid) = config.server_pid {
+        // fails early if the process can't be inspected
+        ServerUsage::sample(pid)?;
+    }
+    let start = Instant::now();
+    let deadline = start + config.duration;
+
+    let mut clients = TaskSet::default();
+    for _ in 0..config.clients {
+        let config = config.clone();
+        clients.spawn(async move {
+            let mut outcomes = Vec::new();
+            while Instant::now() < deadline {
+                outcomes.push(run_session(&config).await?);
+            }
+            Ok::<_, io::Error>(outcomes)
+        });
+    }
+
+    let mut server = config.server_pid.map(|_| ServerUsage::default());
+    let mut sampling = time::interval(SAMPLE_INTERVAL);
+    let mut outcomes = Vec::new();
+    loop {
+        tokio::select! {
+            _ = sampling.tick(), if server.is_some() => {
+                sample_server(config.server_pid, &mut server);
+            }
+            joined = clients.join_next() => match joined {
+                Some(res) => outcomes.extend(res.map_err(io::Error::other)??),
+                None => break,
+            },
+        }
+    }
+    sample_server(config.server_pid, &mut server);
+    let elapsed = start.elapsed();
+
+    let mut failures_by_exit = BTreeMap::new();
+    for failure in outcomes
+        .iter()
+        .filter_map(|outcome| outcome.failure.clone())
+    {
+        *failures_by_exit.entry(failure).or_default() += 1;
+    }
+    let sessions = outcomes.len() as u64;
+    let failures = failures_by_exit.values().sum::<u64>();
+    let mut latencies = outcomes
+        .iter()
+        .map(|outcome| outcome.latency)
+        .collect::<Vec<_>>();
+    Ok(Report {
+        sessions,
+        failures,
+        error_rate: if sessions > 0 {
+            failures as f64 / sessions as f64
+        } else {
+            0.0
+        },
+        sessions_per_second: sessions as f64 / elapsed.as_secs_f64(),
+        latency_ms: Percentiles::of(&mut latencies),
+        failures_by_exit,
+        server,
+    })
+}
+
+fn sample_server(pid: Option<u32>, usage: &mut Option<ServerUsage>) {
+    if let (Some(pid), Some(usage)) = (pid, usage) {
+        match ServerUsage::sample(pid) {
+            Ok(sample) => usage.add_sample(sample),
+            Err(e) => tracing::warn!("failed to sample server {pid}: {e}"),
+        }
+    }
+}
+
+async fn run_session(config: &SoakConfig) -> io::Result<Outcome> {
+    let start = Instant::now();
+    let mut child = process::Command::new(&config.client)
+        .args(["--connect", &config.connect_addr, &config.operation])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let session = async {
+        // a client failing early closes its stdin, which shows up in the exit status
+        let _ = stdin.write_all(&config.input).await;
+        drop(stdin);
+        child.wait().await
+    };
+    let failure = match time::timeout(config.session_timeout, session).await {
+        Ok(status) => {
+            let status = status?;
+            (!status.success()).then(|| status.to_string())
+        }
+        Err(_) => {
+            child.kill().await?;
+            Some("timed out".into())
+        }
+    };
+    Ok(Outcome {
+        latency: start.elapsed(),
+        failure,
+    })
+}
@@ -1,9 +1,11 @@
 use std::{
     fmt::{self, Display},
-    io, iter,
-    os::unix::prelude::{AsRawFd, RawFd},
+    fs::{self, File},
+    io, iter, mem,
+    os::unix::prelude::{AsRawFd, FileTypeExt as _, FromRawFd, OwnedFd, RawFd},
+    path::{Path, PathBuf},
     pin::Pin,
-    task,
+    ptr, task,
 };
 
 use async_trait::async_trait;
@@ -11,13 +13,19 @@ use derive_more::From;
 use tokio::{
     io::{AsyncRead, AsyncWrite, ReadBuf},
     net::{self, tcp, unix, TcpListener, TcpStream, UnixListener, UnixStream},
+    process::{ChildStdin, ChildStdout},
 };
+use tracing::Instrument as _;
+
+use crate::pidfile;
 
 #[async_trait]
 pub trait ToSocketAddrs {
     async fn to_socket_addrs(&self) -> io::Result<Box<dyn Iterator<Item = SocketAddr> + '_>>;
 }
 
+// lint fires on the lifetimes generated by `async_trait`
+#[allow(clippy::needless_lifetimes)]
 #[async_trait]
 impl<T> ToSocketAddrs for &T
 where
@@ -31,13 +39,8 @@ where
 #[async_trait]
 impl ToSocketAddrs for str {
     async fn to_socket_addrs(&self) -> io::Result<Box<dyn Iterator<Item = SocketAddr> + '_>> {
-        let unix_addr = self
-            .strip_prefix("unix:")
-            .or_else(|| self.contains('/').then(|| self));
-
-        if let Some(addr) = unix_addr {
-            let addr = std::os::unix::net::SocketAddr::from_pathname(addr)?;
-            return Ok(Box::new(iter::once(addr.into())));
+        if let Some(addr) = SocketAddr::parse_unix(self) {
+            return Ok(Box::new(iter::once(addr?)));
         }
 
         // TODO: support @name syntax (abstract socket)
@@ -62,6 +65,29 @@ pub enum SocketAddr {
     Inet(std::net::SocketAddr),
 }
 
+impl SocketAddr {
+    /// Parses `unix:<path>`, or a path containing `/`, as a Unix socket address.
+    ///
+    /// Returns `None` if `addr` doesn't look like a Unix socket address.
+    pub fn parse_unix(addr: &str) -> Option<io::Result<Self>> {
+        let path = addr
+            .strip_prefix("unix:")
+            .or_else(|| addr.contains('/').then_some(addr))?;
+        Some(std::os::unix::net::SocketAddr::from_pathname(path).map(Into::into))
+    }
+}
+
+fn require_pathname(path: Option<&Path>) -> io::Result<&Path> {
+    // TODO: support abstract socket
+    // blocked by https://github.com/tokio-rs/tokio/issues/4610
+    path.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "unnamed or abstract socket address is not supported",
+        )
+    })
+}
+
 impl Display for SocketAddr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -69,13 +95,13 @@ impl Display for SocketAddr {
                 Some(path) => write!(f, "unix:{}", path.display()),
                 // TODO: support abstract socket
                 // blocked by `feature(unix_socket_abstract)` https://github.com/rust-lang/rust/issues/85410
-                None => unimplemented!("abstract socket not supported"),
+                None => write!(f, "unix:(unnamed)"),
             },
             Self::UnixTokio(addr) => match addr.as_pathname() {
                 Some(path) => write!(f, "unix:{}", path.display()),
                 // TODO: support abstract socket
                 // blocked by https://github.com/tokio-rs/tokio/issues/4610
-                None => unimplemented!("abstract socket not supported"),
+                None => write!(f, "unix:(unnamed)"),
             },
             Self::Inet(addr) => write!(f, "{addr}"),
         }
@@ -102,18 +128,10 @@ impl SocketListener {
         let mut last_err = None;
         for addr in addrs.to_socket_addrs().await? {
             let res = match addr {
-                SocketAddr::UnixStd(addr) => {
-                    // TODO: support abstract socket
-                    // blocked by https://github.com/tokio-rs/tokio/issues/4610
-                    let path = addr.as_pathname().expect("abstract socket not supported");
-                    UnixListener::bind(path).map(Into::into)
-                }
-                SocketAddr::UnixTokio(addr) => {
-                    // TODO: support abstract socket
-                    // blocked by https://github.com/tokio-rs/tokio/issues/4610
-                    let path = addr.as_pathname().expect("abstract socket not supported");
-                    UnixListener::bind(path).map(Into::into)
-                }
+                SocketAddr::UnixStd(addr) => require_pathname(addr.as_pathname())
+                    .and_then(|path| UnixListener::bind(path).map(Into::into)),
+                SocketAddr::UnixTokio(addr) => require_pathname(addr.as_pathname())
+                    .and_then(|path| UnixListener::bind(path).map(Into::into)),
                 SocketAddr::Inet(addr) => TcpListener::bind(addr).await.map(Into::into),
             };
             match res {
@@ -130,6 +148,37 @@ impl SocketListener {
         }))
     }
 
+    /// Takes over an inherited listening socket, such as one passed by systemd socket activation.
+    ///
+    /// Must be called from a tokio runtime.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open listening socket, not owned by anything else.
+    pub unsafe fn from_raw_fd(fd: RawFd) -> io::Result<Self> {
+        let mut addr = mem::zeroed::<libc::sockaddr_storage>();
+        let mut len = mem::size_of_val(&addr) as libc::socklen_t;
+        if libc::getsockname(fd, ptr::addr_of_mut!(addr).cast(), &mut len) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        match libc::c_int::from(addr.ss_family) {
+            libc::AF_UNIX => {
+                let listener = std::os::unix::net::UnixListener::from_raw_fd(fd);
+                listener.set_nonblocking(true)?;
+                UnixListener::from_std(listener).map(Into::into)
+            }
+            libc::AF_INET | libc::AF_INET6 => {
+                let listener = std::net::TcpListener::from_raw_fd(fd);
+                listener.set_nonblocking(true)?;
+                TcpListener::from_std(listener).map(Into::into)
+            }
+            family => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported socket family: {family}"),
+            )),
+        }
+    }
+
     pub async fn accept(&self) -> io::Result<(SocketStream, SocketAddr)> {
         match self {
             Self::Unix(listener) => listener
@@ -176,6 +225,70 @@ impl SocketListener {
     }
 }
 
+/// Lock on a Unix socket path, held by the server listening on it.
+///
+/// The lock is taken on `<path>.lock` with `flock`, so it is released when the server exits,
+/// however it exits.
+#[derive(Debug)]
+pub struct SocketLock {
+    file: File,
+}
+
+impl AsRawFd for SocketLock {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+/// Takes over a lock held through an inherited file, such as one handed over by the server this
+/// one replaces.
+impl From<OwnedFd> for SocketLock {
+    fn from(fd: OwnedFd) -> Self {
+        Self { file: fd.into() }
+    }
+}
+
+impl SocketLock {
+    /// Locks the socket at `path`, and removes it if a server that is no longer running left it
+    /// behind.
+    ///
+    /// The socket is removed only if it is not locked and refuses connections. Fails with
+    /// [`io::ErrorKind::AddrInUse`] if another server holds the lock.
+    pub fn acquire(path: &Path) -> io::Result<Self> {
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)?;
+        if !pidfile::try_lock(&file)? {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is locked by another server", lock_path.display()),
+            ));
+        }
+
+        match fs::symlink_metadata(path) {
+            // a server started before locks were taken may still be listening on it
+            Ok(metadata) if metadata.file_type().is_socket() => {
+                if let Err(e) = std::os::unix::net::UnixStream::connect(path) {
+                    if e.kind() == io::ErrorKind::ConnectionRefused {
+                        fs::remove_file(path)?;
+                        tracing::info!("removed stale socket {}", path.display());
+                    }
+                }
+            }
+            // anything else is left for bind to fail on
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(Self { file })
+    }
+}
+
 #[derive(Debug, From)]
 pub enum SocketStream {
     Unix(UnixStream),
@@ -240,20 +353,20 @@ impl AsyncWrite for SocketStream {
 impl SocketStream {
     pub async fn connect(addrs: impl ToSocketAddrs) -> io::Result<Self> {
         let mut last_err = None;
-        for addr in addrs.to_socket_addrs().await? {
+        let addrs = addrs
+            .to_socket_addrs()
+            .instrument(tracing::debug_span!("resolve"))
+            .await?;
+        for addr in addrs {
             let res = match addr {
-                SocketAddr::UnixStd(addr) => {
-                    // TODO: support abstract socket
-                    // blocked by https://github.com/tokio-rs/tokio/issues/4610
-                    let path = addr.as_pathname().expect("abstract socket not supported");
-                    UnixStream::connect(path).await.map(Into::into)
-                }
-                SocketAddr::UnixTokio(addr) => {
-                    // TODO: support abstract socket
-                    // blocked by https://github.com/tokio-rs/tokio/issues/4610
-                    let path = addr.as_pathname().expect("abstract socket not supported");
-                    UnixStream::connect(path).await.map(Into::into)
-                }
+                SocketAddr::UnixStd(addr) => match require_pathname(addr.as_pathname()) {
+                    Ok(path) => UnixStream::connect(path).await.map(Into::into),
+                    Err(e) => Err(e),
+                },
+                SocketAddr::UnixTokio(addr) => match require_pathname(addr.as_pathname()) {
+                    Ok(path) => UnixStream::connect(path).await.map(Into::into),
+                    Err(e) => Err(e),
+                },
                 SocketAddr::Inet(addr) => TcpStream::connect(addr).await.map(Into::into),
             };
             match res {
@@ -320,6 +433,7 @@ impl SocketStream {
 pub enum OwnedReadHalf {
     Tcp(tcp::OwnedReadHalf),
     Unix(unix::OwnedReadHalf),
+    Child(ChildStdout),
 }
 
 impl AsyncRead for OwnedReadHalf {
@@ -331,6 +445,7 @@ impl AsyncRead for OwnedReadHalf {
         match self.get_mut() {
             Self::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
             Self::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Child(stream) => Pin::new(stream).poll_read(cx, buf),
         }
     }
 }
@@ -339,6 +454,7 @@ impl AsyncRead for OwnedReadHalf {
 pub enum OwnedWriteHalf {
     Tcp(tcp::OwnedWriteHalf),
     Unix(unix::OwnedWriteHalf),
+    Child(ChildStdin),
 }
 
 impl AsyncWrite for OwnedWriteHalf {
@@ -350,6 +466,7 @@ impl AsyncWrite for OwnedWriteHalf {
         match self.get_mut() {
             Self::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
             Self::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Child(stream) => Pin::new(stream).poll_write(cx, buf),
         }
     }
 
@@ -360,6 +477,7 @@ impl AsyncWrite for OwnedWriteHalf {
         match self.get_mut() {
             Self::Unix(stream) => Pin::new(stream).poll_flush(cx),
             Self::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Child(stream) => Pin::new(stream).poll_flush(cx),
         }
     }
 
@@ -370,6 +488,7 @@ impl AsyncWrite for OwnedWriteHalf {
         match self.get_mut() {
             Self::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
             Self::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Child(stream) => Pin::new(stream).poll_shutdown(cx),
         }
     }
 }
@@ -0,0 +1,120 @@
+use std::{env, ffi::OsStr, io, os::unix::ffi::OsStrExt as _, str::FromStr};
+
+use tokio::process;
+
+use crate::{protocol::Command, template};
+
+/// Command run by the server for one credential operation instead of `git credential`.
+#[derive(Debug, Clone)]
+pub struct CommandOverride {
+    pub command: Command,
+    pub template: String,
+}
+
+impl FromStr for CommandOverride {
+    type Err = String;
+
+    /// Parses `OPERATION=TEMPLATE`, where `OPERATION` is `get`, `store` or `erase`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, template) = s
+            .split_once('=')
+            .ok_or("expected OPERATION=COMMAND, e.g. get='git credential fill'")?;
+        let command = match name {
+            "get" => Command::Get,
+            "store" => Command::Store,
+            "erase" => Command::Erase,
+            _ => {
+                return Err(format!(
+                    "unknown operation {name:?}, expected `get`, `store` or `erase`"
+                ))
+            }
+        };
+        let template = template.to_owned();
+        expand(&template, command).map_err(|e| e.to_string())?;
+        Ok(Self { command, template })
+    }
+}
+
+/// Expands the tokens in a command template.
+///
+/// Supported tokens are `%o` (the `git credential` operation, e.g. `fill`), `%c` (the client's
+/// command, e.g. `get`) and `%%` (a literal `%`).
+pub fn expand(template: &str, command: Command) -> io::Result<String> {
+    let [_, operation] = command.git_args();
+    let name = match command {
+        Command::Get => "get",
+        Command::Store => "store",
+        Command::Erase => "erase",
+    };
+
+    template::expand(template, "command template", |token| match token {
+        'o' => Some(operation),
+        'c' => Some(name),
+        _ => None,
+    })
+}
+
+/// Builds the process run for `command`.
+///
+/// The last override for the command is run through `sh -c`; without one, `git credential` is
+/// run directly.
+pub fn command(command: Command, overrides: &[CommandOverride]) -> io::Result<process::Command> {
+    match overrides.iter().rev().find(|o| o.command == command) {
+        Some(CommandOverride { template, .. }) => {
+            let expanded = expand(template, command)?;
+            let mut cmd = process::Command::new("sh");
+            cmd.args(["-c", &expanded]);
+            Ok(cmd)
+        }
+        None => {
+            let mut cmd = process::Command::new("git");
+            cmd.args(command.git_args());
+            Ok(cmd)
+        }
+    }
+}
+
+/// Variables of the server's environment passed to git by default.
+///
+/// Besides what git and `sh` need to run, these let credential helpers reach the user's session
+/// (e.g. libsecret through D-Bus) and let administrators configure git with `GIT_*` variables.
+pub const DEFAULT_ENV_ALLOW_LIST: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "SHELL",
+    "LANG",
+    "LANGUAGE",
+    "LC_*",
+    "TZ",
+    "TMPDIR",
+    "XDG_RUNTIME_DIR",
+    "DBUS_SESSION_BUS_ADDRESS",
+    "GIT_*",
+];
+
+/// Returns whether the variable `name` matches a pattern of `allow_list`.
+///
+/// A pattern is a variable name, or a prefix followed by `*` matching every variable starting
+/// with the prefix.
+pub fn is_allowed(name: &OsStr, allow_list: &[impl AsRef<str>]) -> bool {
+    allow_list.iter().any(|pattern| {
+        let pattern = pattern.as_ref().as_bytes();
+        match pattern.strip_suffix(b"*") {
+            Some(prefix) => name.as_bytes().starts_with(prefix),
+            None => name.as_bytes() == pattern,
+        }
+    })
+}
+
+/// Runs `cmd` with only the variables of the server's environment matching `allow_list`, in the
+/// root directory.
+///
+/// Variables set on `cmd` afterwards are passed as usual. Running in `/` keeps the configuration
+/// of a repository the server happens to be started in away from git.
+pub fn scrub_env(cmd: &mut process::Command, allow_list: &[impl AsRef<str>]) {
+    cmd.env_clear()
+        .envs(env::vars_os().filter(|(name, _)| is_allowed(name, allow_list)))
+        .current_dir("/");
+}
@@ -0,0 +1,107 @@
+use std::{
+    io,
+    os::unix::prelude::{AsRawFd, RawFd},
+    pin::Pin,
+    task::{self, ready},
+};
+
+use tokio::io::{unix::AsyncFd, AsyncWrite};
+
+/// Asynchronous stdout registered with the runtime's reactor.
+///
+/// The file descriptor is switched to nonblocking mode while this value is alive, so output is
+/// copied without hopping to a blocking thread for every chunk. The original file status flags are
+/// restored on drop.
+#[derive(Debug)]
+pub struct AsyncStdio {
+    inner: AsyncFd<NonBlockingFd>,
+}
+
+#[derive(Debug)]
+struct NonBlockingFd {
+    fd: RawFd,
+    flags: libc::c_int,
+}
+
+impl AsRawFd for NonBlockingFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for NonBlockingFd {
+    fn drop(&mut self) {
+        unsafe { libc::fcntl(self.fd, libc::F_SETFL, self.flags) };
+    }
+}
+
+impl AsyncStdio {
+    /// Wraps stdout, which the helper owns; stderr is left alone, as it is usually shared with git
+    /// and the user's shell.
+    pub fn stdout() -> io::Result<Option<Self>> {
+        Self::new(libc::STDOUT_FILENO)
+    }
+
+    /// Wraps `fd` if it is a pipe or a socket.
+    ///
+    /// Returns `None` for other file types: terminals are shared with other processes, which must
+    /// not observe nonblocking mode, and regular files can't be polled.
+    fn new(fd: RawFd) -> io::Result<Option<Self>> {
+        let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+        if unsafe { libc::fstat(fd, &mut stat) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let file_type = stat.st_mode & libc::S_IFMT;
+        if file_type != libc::S_IFIFO && file_type != libc::S_IFSOCK {
+            return Ok(None);
+        }
+
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let inner = AsyncFd::new(NonBlockingFd { fd, flags })?;
+        Ok(Some(Self { inner }))
+    }
+}
+
+impl AsyncWrite for AsyncStdio {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> task::Poll<Result<usize, io::Error>> {
+        loop {
+            let mut guard = ready!(self.inner.poll_write_ready(cx))?;
+            let res = guard.try_io(|inner| {
+                let fd = inner.get_ref().fd;
+                let len = unsafe { libc::write(fd, buf.as_ptr().cast(), buf.len()) };
+                if len < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(len as usize)
+            });
+            match res {
+                Ok(res) => return task::Poll::Ready(res),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        _cx: &mut task::Context<'_>,
+    ) -> task::Poll<Result<(), io::Error>> {
+        task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        _cx: &mut task::Context<'_>,
+    ) -> task::Poll<Result<(), io::Error>> {
+        task::Poll::Ready(Ok(()))
+    }
+}
@@ -1,22 +1,99 @@
-use std::{io, sync::Arc};
+use std::{future::Future, io, sync::Arc};
 
 use bytes::BytesMut;
 use color_eyre::eyre::{self, eyre, WrapErr as _};
+use futures::{stream::FuturesUnordered, StreamExt as _};
 use tokio::{
     io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _},
     sync::mpsc,
+    task::{JoinError, JoinHandle},
 };
 
-const BUFFER_SIZE: usize = 4 * 1024;
+/// Tasks owned together, aborted when the set is dropped so that none outlives its owner.
+///
+/// Finished tasks are kept until they are joined, so a long-lived owner has to keep calling
+/// [`TaskSet::join_next`].
+#[derive(Debug)]
+pub struct TaskSet<T> {
+    tasks: FuturesUnordered<JoinHandle<T>>,
+}
+
+impl<T> Default for TaskSet<T> {
+    fn default() -> Self {
+        Self {
+            tasks: FuturesUnordered::new(),
+        }
+    }
+}
+
+impl<T: Send + 'static> TaskSet<T> {
+    /// Spawns `future` as a task of the set.
+    pub fn spawn<F>(&mut self, future: F)
+    where
+        F: Future<Output = T> + Send + 'static,
+    {
+        self.tasks.push(tokio::spawn(future));
+    }
+
+    /// Returns the number of tasks not joined yet.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Returns `true` if all the tasks have been joined.
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Waits for the next task to finish, or returns `None` if the set is empty.
+    pub async fn join_next(&mut self) -> Option<Result<T, JoinError>> {
+        self.tasks.next().await
+    }
+
+    /// Waits for all the tasks to finish, failing with the first panic among them.
+    ///
+    /// Aborted tasks are not failures.
+    pub async fn join_all(&mut self) -> Result<Vec<T>, JoinError> {
+        let mut outputs = vec![];
+        let mut panic = None;
+        while let Some(res) = self.join_next().await {
+            match res {
+                Ok(output) => outputs.push(output),
+                Err(e) if e.is_panic() => panic = panic.or(Some(e)),
+                Err(_) => {}
+            }
+        }
+        match panic {
+            Some(e) => Err(e),
+            None => Ok(outputs),
+        }
+    }
+}
+
+impl<T> TaskSet<T> {
+    /// Aborts all the tasks; they still have to be joined.
+    pub fn abort_all(&self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+impl<T> Drop for TaskSet<T> {
+    fn drop(&mut self) {
+        self.abort_all();
+    }
+}
 
 #[tracing::instrument(level = "debug", err, ret, skip_all)]
 pub async fn input(
     mut input: impl AsyncRead + Unpin,
+    buffer_size: usize,
     tx: mpsc::Sender<Arc<BytesMut>>,
     mut rx: mpsc::Receiver<Result<(), String>>,
 ) -> eyre::Result<()> {
     let mut bytes = BytesMut::new();
-    bytes.resize(BUFFER_SIZE, 0);
+    bytes.resize(buffer_size, 0);
     loop {
         match input.read(&mut bytes).await {
             Ok(0) => {
@@ -39,7 +116,7 @@ pub async fn input(
 
                 let send_bytes = Arc::try_unwrap(send_bytes).expect("must be un-shared");
                 bytes.unsplit(send_bytes);
-                assert_eq!(bytes.len(), BUFFER_SIZE);
+                assert_eq!(bytes.len(), buffer_size);
             }
             Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
             Err(e) => return Err(eyre!(e).wrap_err("failed to read stdin")),
@@ -57,6 +134,8 @@ pub async fn output(
     while let Some(bytes) = rx.recv().await {
         tracing::trace!("{} bytes received", bytes.len());
         let res = output.write_all(&bytes).await;
+        // release the buffer before acknowledging, so that the sender can reuse it
+        drop(bytes);
         let res = match &res {
             Ok(()) => {
                 tracing::trace!("bytes written");
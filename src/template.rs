@@ -0,0 +1,39 @@
+use std::io;
+
+/// Replaces each `%X` token of `template` with `lookup(X)`, and `%%` with a literal `%`.
+///
+/// `what` names the template in errors, which are returned for a token `lookup` doesn't know
+/// and for a trailing `%`.
+pub fn expand<'a>(
+    template: &str,
+    what: &str,
+    lookup: impl Fn(char) -> Option<&'a str>,
+) -> io::Result<String> {
+    let mut expanded = String::with_capacity(template.len());
+    let mut chars = template.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            expanded.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => expanded.push('%'),
+            Some(ch) => match lookup(ch) {
+                Some(value) => expanded.push_str(value),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("unknown token in {what}: %{ch}"),
+                    ))
+                }
+            },
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{what} ends with an incomplete token"),
+                ))
+            }
+        }
+    }
+    Ok(expanded)
+}
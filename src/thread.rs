@@ -7,16 +7,15 @@ use bytes::BytesMut;
 use color_eyre::eyre::{self, eyre, WrapErr as _};
 use tokio::sync::mpsc;
 
-const BUFFER_SIZE: usize = 4 * 1024;
-
 #[tracing::instrument(level = "debug", err, ret, skip_all)]
 pub fn input(
     mut input: impl Read,
+    buffer_size: usize,
     tx: mpsc::Sender<Arc<BytesMut>>,
     mut rx: mpsc::Receiver<Result<(), String>>,
 ) -> eyre::Result<()> {
     let mut bytes = BytesMut::new();
-    bytes.resize(BUFFER_SIZE, 0);
+    bytes.resize(buffer_size, 0);
     loop {
         match input.read(&mut bytes) {
             Ok(0) => {
@@ -37,7 +36,7 @@ pub fn input(
 
                 let send_bytes = Arc::try_unwrap(send_bytes).expect("must be un-shared");
                 bytes.unsplit(send_bytes);
-                assert_eq!(bytes.len(), BUFFER_SIZE);
+                assert_eq!(bytes.len(), buffer_size);
             }
             Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
             Err(e) => return Err(eyre!(e).wrap_err("failed to read stdin")),
@@ -55,6 +54,8 @@ pub fn output(
     while let Some(bytes) = rx.blocking_recv() {
         tracing::trace!("{} bytes received", bytes.len());
         let res = output.write_all(&bytes);
+        // release the buffer before acknowledging, so that the sender can reuse it
+        drop(bytes);
         let res = match &res {
             Ok(()) => {
                 tracing::trace!("bytes written");
@@ -0,0 +1,202 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Write},
+    process,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// Recorder of the timings of spans, written in the Chrome trace event format.
+///
+/// Each span becomes a complete (`X`) event lasting from its creation to its closing, or to the
+/// writing of the trace if it is still open then. Spans running concurrently under the same parent
+/// are put on separate rows (`tid`), so that the rows nest as the viewers expect.
+#[derive(Debug, Clone)]
+pub struct ChromeTrace {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    start: Instant,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    open: HashMap<Id, OpenSpan>,
+    closed: Vec<TraceEvent>,
+    rows: u64,
+}
+
+#[derive(Debug)]
+struct OpenSpan {
+    name: &'static str,
+    target: &'static str,
+    parent: Option<Id>,
+    start: Instant,
+    row: u64,
+    open_children: usize,
+    args: Map<String, Value>,
+}
+
+#[derive(Debug, Serialize)]
+struct TraceEvent {
+    name: &'static str,
+    cat: &'static str,
+    ph: &'static str,
+    /// Start, in microseconds since the recorder was created
+    ts: f64,
+    /// Duration, in microseconds
+    dur: f64,
+    pid: u32,
+    tid: u64,
+    args: Map<String, Value>,
+}
+
+impl Default for ChromeTrace {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                start: Instant::now(),
+                state: Mutex::default(),
+            }),
+        }
+    }
+}
+
+impl ChromeTrace {
+    /// Writes the events of the spans recorded so far as a JSON object.
+    ///
+    /// Spans still open end now, with an `unfinished` argument.
+    pub fn write(&self, writer: impl Write) -> io::Result<()> {
+        let now = Instant::now();
+        let state = self.inner.state.lock().unwrap();
+        let open = state
+            .open
+            .values()
+            .map(|span| {
+                let mut event = self.event(span, now);
+                event.args.insert("unfinished".into(), true.into());
+                event
+            })
+            .collect::<Vec<_>>();
+        let events = state.closed.iter().chain(&open).collect::<Vec<_>>();
+        serde_json::to_writer(
+            writer,
+            &serde_json::json!({
+                "traceEvents": events,
+                "displayTimeUnit": "ms",
+            }),
+        )?;
+        Ok(())
+    }
+
+    fn event(&self, span: &OpenSpan, end: Instant) -> TraceEvent {
+        TraceEvent {
+            name: span.name,
+            cat: span.target,
+            ph: "X",
+            ts: span.start.duration_since(self.inner.start).as_secs_f64() * 1e6,
+            dur: end.duration_since(span.start).as_secs_f64() * 1e6,
+            pid: process::id(),
+            tid: span.row,
+            args: span.args.clone(),
+        }
+    }
+}
+
+impl<S> Layer<S> for ChromeTrace
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let parent = ctx
+            .span(id)
+            .and_then(|span| span.parent())
+            .map(|parent| parent.id());
+        let mut args = Map::new();
+        attrs.record(&mut ArgsVisitor(&mut args));
+
+        let mut state = self.inner.state.lock().unwrap();
+        // the first child of a span stays on its parent's row, concurrent ones get a new row
+        let parent_row = parent
+            .as_ref()
+            .and_then(|parent| state.open.get_mut(parent))
+            .and_then(|parent| {
+                parent.open_children += 1;
+                (parent.open_children == 1).then_some(parent.row)
+            });
+        let row = parent_row.unwrap_or_else(|| {
+            state.rows += 1;
+            state.rows
+        });
+        state.open.insert(
+            id.clone(),
+            OpenSpan {
+                name: attrs.metadata().name(),
+                target: attrs.metadata().target(),
+                parent,
+                start: Instant::now(),
+                row,
+                open_children: 0,
+                args,
+            },
+        );
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+        if let Some(span) = self.inner.state.lock().unwrap().open.get_mut(id) {
+            values.record(&mut ArgsVisitor(&mut span.args));
+        }
+    }
+
+    fn on_close(&self, id: Id, _ctx: Context<'_, S>) {
+        let now = Instant::now();
+        let mut state = self.inner.state.lock().unwrap();
+        let Some(span) = state.open.remove(&id) else {
+            return;
+        };
+        if let Some(parent) = span.parent.as_ref().and_then(|id| state.open.get_mut(id)) {
+            parent.open_children -= 1;
+        }
+        let event = self.event(&span, now);
+        state.closed.push(event);
+    }
+}
+
+/// Collects the fields of a span as the arguments of its event.
+struct ArgsVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for ArgsVisitor<'_> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().into(), format!("{value:?}").into());
+    }
+}
@@ -0,0 +1,164 @@
+use std::{io, time::Duration};
+
+use tokio::{process::Child, time};
+
+use crate::{
+    dial,
+    socket::{OwnedReadHalf, OwnedWriteHalf, SocketStream},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConnectError {
+    #[error("timed out connecting to {addr}")]
+    Timeout { addr: String },
+    #[error("failed to spawn dial command: {command}")]
+    Dial {
+        command: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("failed to connect socket: {addr}")]
+    Connect {
+        addr: String,
+        #[source]
+        source: io::Error,
+    },
+}
+
+/// How the client reaches a server.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), git_remote_utils::transport::ConnectError> {
+/// use std::time::Duration;
+/// use git_remote_utils::transport::Transport;
+///
+/// let connection = Transport::builder()
+///     .dial_command("ssh -W %h:%p bastion")
+///     .timeout(Duration::from_secs(10))
+///     .retry(3, Duration::from_millis(500))
+///     .connect("10.0.0.1:9419")
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Transport {
+    dial_command: Option<String>,
+    timeout: Option<Duration>,
+    retries: u32,
+    retry_delay: Duration,
+}
+
+/// Builder of [`Transport`].
+#[derive(Debug, Clone, Default)]
+pub struct TransportBuilder {
+    transport: Transport,
+}
+
+/// Connection to a server.
+#[derive(Debug)]
+pub struct Connection {
+    pub read: OwnedReadHalf,
+    pub write: OwnedWriteHalf,
+    /// Process of the dial command, killed when dropped
+    pub dial_child: Option<Child>,
+}
+
+impl Transport {
+    pub fn builder() -> TransportBuilder {
+        TransportBuilder::default()
+    }
+
+    /// Connects to the server at `addr`, through the dial command if one is set.
+    pub async fn connect(&self, addr: &str) -> Result<Connection, ConnectError> {
+        match self.timeout {
+            Some(timeout) => time::timeout(timeout, self.connect_now(addr))
+                .await
+                .map_err(|_| ConnectError::Timeout { addr: addr.into() })?,
+            None => self.connect_now(addr).await,
+        }
+    }
+
+    async fn connect_now(&self, addr: &str) -> Result<Connection, ConnectError> {
+        let mut retries = self.retries;
+        loop {
+            match self.connect_once(addr).await {
+                Err(ConnectError::Connect { source, .. }) if retries > 0 => {
+                    retries -= 1;
+                    tracing::debug!("failed to connect to {addr}, retrying: {source}");
+                    time::sleep(self.retry_delay).await;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    async fn connect_once(&self, addr: &str) -> Result<Connection, ConnectError> {
+        match &self.dial_command {
+            Some(command) => {
+                let (child, read, write) =
+                    dial::spawn(command, addr).map_err(|source| ConnectError::Dial {
+                        command: command.clone(),
+                        source,
+                    })?;
+                Ok(Connection {
+                    read,
+                    write,
+                    dial_child: Some(child),
+                })
+            }
+            None => {
+                let stream =
+                    SocketStream::connect(addr)
+                        .await
+                        .map_err(|source| ConnectError::Connect {
+                            addr: addr.into(),
+                            source,
+                        })?;
+                let (read, write) = stream.into_split();
+                Ok(Connection {
+                    read,
+                    write,
+                    dial_child: None,
+                })
+            }
+        }
+    }
+}
+
+impl TransportBuilder {
+    /// Reaches the server through the stdio of `command` instead of connecting directly.
+    ///
+    /// See [`dial::expand`] for the tokens replaced in the command.
+    pub fn dial_command(mut self, command: impl Into<String>) -> Self {
+        self.transport.dial_command = Some(command.into());
+        self
+    }
+
+    /// Gives up connecting after `timeout`, retries included.
+    ///
+    /// With a dial command, this only covers spawning the command: the server speaks only after
+    /// the client's handshake, so whether the command reached it isn't known here.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.transport.timeout = Some(timeout);
+        self
+    }
+
+    /// Tries connecting the socket up to `retries` more times, `delay` apart, when it fails.
+    ///
+    /// Failing to spawn the dial command is not retried.
+    pub fn retry(mut self, retries: u32, delay: Duration) -> Self {
+        self.transport.retries = retries;
+        self.transport.retry_delay = delay;
+        self
+    }
+
+    pub fn build(self) -> Transport {
+        self.transport
+    }
+
+    /// Connects to the server at `addr` with the configured options.
+    pub async fn connect(self, addr: &str) -> Result<Connection, ConnectError> {
+        self.transport.connect(addr).await
+    }
+}
@@ -0,0 +1,11 @@
+/// Version followed by how the package was built, shown by `--version` (`-V` shows the version
+/// alone).
+pub const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    "\ntarget: ",
+    env!("GRU_BUILD_TARGET"),
+    "\nprofile: ",
+    env!("GRU_BUILD_PROFILE"),
+    "\nfeatures: ",
+    env!("GRU_BUILD_FEATURES"),
+);
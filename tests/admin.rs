@@ -0,0 +1,48 @@
+use std::{sync::Arc, time::Duration};
+
+use git_remote_utils::admin::Sessions;
+use tokio::time::{self, Instant};
+
+const IDLE: Duration = Duration::from_millis(100);
+
+#[tokio::test]
+async fn idle_without_sessions() {
+    let sessions = Arc::new(Sessions::default());
+    let start = Instant::now();
+    sessions.wait_idle(IDLE).await;
+    assert!(start.elapsed() >= IDLE);
+}
+
+#[tokio::test]
+async fn not_idle_while_a_session_runs() {
+    let sessions = Arc::new(Sessions::default());
+    let session = sessions.register(0, "peer".into());
+    assert!(time::timeout(3 * IDLE, sessions.wait_idle(IDLE))
+        .await
+        .is_err());
+
+    let idle = tokio::spawn({
+        let sessions = Arc::clone(&sessions);
+        async move { sessions.wait_idle(IDLE).await }
+    });
+    time::sleep(IDLE / 2).await;
+    let ended = Instant::now();
+    drop(session);
+    idle.await.unwrap();
+    assert!(ended.elapsed() >= IDLE);
+}
+
+#[tokio::test]
+async fn new_session_restarts_the_timeout() {
+    let sessions = Arc::new(Sessions::default());
+    let idle = tokio::spawn({
+        let sessions = Arc::clone(&sessions);
+        async move { sessions.wait_idle(IDLE).await }
+    });
+    time::sleep(IDLE / 2).await;
+    let session = sessions.register(0, "peer".into());
+    time::sleep(IDLE).await;
+    assert!(!idle.is_finished());
+    drop(session);
+    idle.await.unwrap();
+}
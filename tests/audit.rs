@@ -0,0 +1,112 @@
+use std::{
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use git_remote_utils::{
+    admin::{SessionInfo, Sessions},
+    audit::{self, AuditError, AuditLog, AuditWriter},
+};
+
+/// Path of a fresh log in the temporary directory, unique to the test.
+fn log_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("gru-audit-{}-{name}.log", std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+/// Records `count` sessions in the log at `path`.
+fn record_sessions(path: &Path, count: u64) {
+    let writer = AuditWriter::spawn(AuditLog::open(path).unwrap()).unwrap();
+    let sessions = Arc::new(Sessions::default().with_audit_log(writer));
+    for id in 0..count {
+        let session = sessions.register(id, format!("peer{id}"));
+        session.update(|info| info.url = Some("https://example.com/repo.git".into()));
+    }
+}
+
+fn verify(log: &str) -> Result<audit::Verified, AuditError> {
+    audit::verify(Cursor::new(log))
+}
+
+#[test]
+fn chain_continues_across_restarts() {
+    let path = log_path("restart");
+    record_sessions(&path, 2);
+    record_sessions(&path, 1);
+    let log = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let verified = verify(&log).unwrap();
+    assert_eq!(verified.records, 3);
+    assert_eq!(verify("").unwrap().last_hash, audit::GENESIS_HASH);
+}
+
+#[test]
+fn altered_record_is_detected() {
+    let path = log_path("altered");
+    record_sessions(&path, 3);
+    let log = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let altered = log.replacen("peer1", "peer9", 1);
+    assert!(matches!(
+        verify(&altered),
+        Err(AuditError::HashMismatch { line: 2 })
+    ));
+}
+
+#[test]
+fn removed_record_is_detected() {
+    let path = log_path("removed");
+    record_sessions(&path, 3);
+    let log = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let lines = log.lines().collect::<Vec<_>>();
+    let removed = [lines[0], lines[2]].join("\n");
+    assert!(matches!(
+        verify(&removed),
+        Err(AuditError::BrokenChain { line: 2 })
+    ));
+    let reordered = [lines[1], lines[0], lines[2]].join("\n");
+    assert!(matches!(
+        verify(&reordered),
+        Err(AuditError::BrokenChain { line: 1 })
+    ));
+}
+
+#[test]
+fn durations_are_hashed_as_written() {
+    // serde_json without `float_roundtrip` reads the first one back as a different number
+    let path = log_path("duration");
+    let log = AuditLog::open(&path).unwrap();
+    for duration in [7.5505073849999995, 0.1 + 0.2, f64::MIN_POSITIVE] {
+        log.append(&SessionInfo {
+            id: 0,
+            session_id: None,
+            peer: "peer".into(),
+            url: None,
+            key: None,
+            command: None,
+            priority: None,
+            pid: None,
+            exit: None,
+            bytes_received: 0,
+            bytes_sent: 0,
+            started_at: 0,
+            duration,
+        })
+        .unwrap();
+    }
+    drop(log);
+    // reopening reads the hash of the last record back
+    drop(AuditLog::open(&path).unwrap());
+    let log = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert!(log.contains("7.5505073849999995"), "{log}");
+    assert_eq!(verify(&log).unwrap().records, 3);
+}
@@ -0,0 +1,53 @@
+use std::sync::{Arc, Mutex};
+
+use git_remote_utils::{budget::Budget, protocol::Priority};
+use tokio::task;
+
+/// Queues sessions behind a held permit and returns the order they are admitted in.
+async fn admission_order(weight: usize, waiting: &[Priority]) -> Vec<Priority> {
+    let budget = Budget::new(1, weight);
+    let held = budget.acquire(1, Priority::Batch).await;
+    let order = Arc::new(Mutex::new(vec![]));
+    let mut tasks = vec![];
+    for &priority in waiting {
+        let budget = Arc::clone(&budget);
+        let order = Arc::clone(&order);
+        tasks.push(tokio::spawn(async move {
+            let _permit = budget.acquire(1, priority).await;
+            order.lock().unwrap().push(priority);
+        }));
+        // let the session enqueue itself before the next one
+        task::yield_now().await;
+    }
+    drop(held);
+    for task in tasks {
+        task.await.unwrap();
+    }
+    let order = order.lock().unwrap().clone();
+    order
+}
+
+#[tokio::test]
+async fn interactive_sessions_are_admitted_by_weight() {
+    use Priority::{Batch as B, Interactive as I};
+    let order = admission_order(2, &[B, B, B, I, I, I, I, I]).await;
+    assert_eq!(order, [I, I, B, I, I, B, I, B]);
+}
+
+#[tokio::test]
+async fn batch_sessions_are_admitted_when_alone() {
+    let order = admission_order(4, &[Priority::Batch; 3]).await;
+    assert_eq!(order, [Priority::Batch; 3]);
+}
+
+#[tokio::test]
+async fn used_bytes_follow_permits() {
+    let budget = Budget::new(10, 1);
+    let first = budget.acquire(3, Priority::Batch).await;
+    let second = budget.acquire(4, Priority::Interactive).await;
+    assert_eq!(budget.used(), 7);
+    drop(first);
+    assert_eq!(budget.used(), 4);
+    drop(second);
+    assert_eq!(budget.used(), 0);
+}
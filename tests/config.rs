@@ -0,0 +1,129 @@
+use std::{env, time::Duration};
+
+use git_remote_utils::{
+    config::{self, ClientOptions, ConfigError},
+    protocol::{Priority, MAX_BUFFER_SIZE},
+};
+
+fn duration(value: &str) -> Result<Duration, ConfigError> {
+    config::parse_duration("timeout", value.into())
+}
+
+#[test]
+fn durations_default_to_seconds() {
+    assert_eq!(duration("0").unwrap(), Duration::ZERO);
+    assert_eq!(duration("30").unwrap(), Duration::from_secs(30));
+    assert_eq!(duration("30s").unwrap(), Duration::from_secs(30));
+    assert_eq!(duration("500ms").unwrap(), Duration::from_millis(500));
+    assert_eq!(duration("2m").unwrap(), Duration::from_secs(120));
+}
+
+#[test]
+fn invalid_durations_are_rejected() {
+    for value in ["", "s", "-1", "1.5s", "30S", "30 s", "5sec", "2h"] {
+        let e = duration(value).unwrap_err();
+        assert!(
+            matches!(&e, ConfigError::InvalidValue { name: "timeout", value: v, .. } if v == value),
+            "{value:?}: {e}"
+        );
+    }
+    assert_eq!(
+        duration(&format!("{}m", u64::MAX)).unwrap_err().to_string(),
+        format!(
+            "invalid value for timeout: \"{}m\" (duration is too long)",
+            u64::MAX
+        )
+    );
+    assert_eq!(
+        duration("99999999999999999999").unwrap_err().to_string(),
+        "invalid value for timeout: \"99999999999999999999\" \
+         (expected a number followed by an optional unit)"
+    );
+}
+
+#[test]
+fn buffer_sizes_are_positive_and_fit_in_a_frame() {
+    assert_eq!(
+        config::parse_buffer_size("buffer", "4096".into()).unwrap(),
+        4096
+    );
+    let max = MAX_BUFFER_SIZE.to_string();
+    assert_eq!(
+        config::parse_buffer_size("buffer", max).unwrap(),
+        MAX_BUFFER_SIZE
+    );
+    let e = config::parse_buffer_size("buffer", (MAX_BUFFER_SIZE + 1).to_string()).unwrap_err();
+    assert!(e.to_string().contains("must be at most"), "{e}");
+    for value in ["", "0", "-1", "4k", " 4096"] {
+        assert!(
+            config::parse_buffer_size("buffer", value.into()).is_err(),
+            "{value:?}"
+        );
+    }
+}
+
+#[test]
+fn priorities_ignore_case() {
+    for (value, priority) in [
+        ("interactive", Priority::Interactive),
+        ("Interactive", Priority::Interactive),
+        ("BATCH", Priority::Batch),
+        ("bAtCh", Priority::Batch),
+    ] {
+        assert_eq!(
+            config::parse_priority("priority", value.into()).unwrap(),
+            priority
+        );
+    }
+    for value in ["", "interactiv", "background"] {
+        assert!(
+            config::parse_priority("priority", value.into()).is_err(),
+            "{value:?}"
+        );
+    }
+}
+
+#[test]
+fn command_line_overrides_environment_overrides_git_config() {
+    // the only test of this binary touching the environment
+    env::set_var("GRU_CREDENTIAL_HELPER_TIMEOUT", "500ms");
+    env::set_var("GRU_CREDENTIAL_HELPER_PRIORITY", "batch");
+    env::set_var("GRU_CREDENTIAL_HELPER_IDENTITY", "env-key");
+    let env_options = ClientOptions::from_env().unwrap();
+    env::remove_var("GRU_CREDENTIAL_HELPER_TIMEOUT");
+    env::remove_var("GRU_CREDENTIAL_HELPER_PRIORITY");
+    env::remove_var("GRU_CREDENTIAL_HELPER_IDENTITY");
+
+    let cli_options = ClientOptions {
+        connect_addr: Some("cli:9419".into()),
+        identity: Some("cli-key".into()),
+        ..Default::default()
+    };
+    let git_options = ClientOptions {
+        connect_addr: Some("git:9419".into()),
+        dial_command: Some("ssh %h nc %p".into()),
+        timeout: Some(Duration::from_secs(10)),
+        buffer_size: Some(8192),
+        priority: Some(Priority::Interactive),
+        ..Default::default()
+    };
+
+    let options = cli_options.or(env_options).or(git_options);
+    assert_eq!(options.connect_addr.as_deref(), Some("cli:9419"));
+    assert_eq!(options.identity.as_deref(), Some("cli-key"));
+    assert_eq!(options.timeout, Some(Duration::from_millis(500)));
+    assert_eq!(options.priority, Some(Priority::Batch));
+    assert_eq!(options.dial_command.as_deref(), Some("ssh %h nc %p"));
+    assert_eq!(options.buffer_size, Some(8192));
+    assert_eq!(options.log, None);
+}
+
+#[test]
+fn invalid_environment_values_are_typed_errors() {
+    let e = config::parse_priority("GRU_CREDENTIAL_HELPER_PRIORITY", "urgent".into()).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "invalid value for GRU_CREDENTIAL_HELPER_PRIORITY: \"urgent\" \
+         (expected `interactive` or `batch`)"
+    );
+}
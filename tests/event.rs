@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use git_remote_utils::{
+    admin::Sessions,
+    event::{EventQueue, Target},
+    protocol::Exit,
+};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncReadExt as _, AsyncWriteExt as _, BufReader},
+    net::TcpListener,
+};
+
+/// Runs a session that spawns its command and exits with `exit`.
+fn run_session(events: EventQueue, exit: Option<Exit>) {
+    let sessions = Arc::new(Sessions::default().with_events(events));
+    let session = sessions.register(0, "peer".into());
+    session.update(|info| info.pid = Some(42));
+    session.emit_started();
+    session.update(|info| info.exit = exit);
+}
+
+#[test]
+fn targets_are_parsed() {
+    assert_eq!(
+        "http://example.com/hooks/git".parse(),
+        Ok(Target::Webhook {
+            authority: "example.com:80".into(),
+            path: "/hooks/git".into(),
+        })
+    );
+    assert_eq!(
+        "nats://[::1]/git.sessions".parse(),
+        Ok(Target::Nats {
+            authority: "[::1]:4222".into(),
+            subject: "git.sessions".into(),
+        })
+    );
+    assert!("nats://localhost:4222/".parse::<Target>().is_err());
+    assert!("https://example.com/".parse::<Target>().is_err());
+}
+
+#[tokio::test]
+async fn webhook_receives_lifecycle_events() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = format!("http://{}/events", listener.local_addr().unwrap());
+    let target = target.parse::<Target>().unwrap();
+    run_session(
+        EventQueue::spawn(vec![target.into_emitter()]),
+        Some(Exit::Code(0)),
+    );
+
+    for expected in ["session-started", "session-completed"] {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+        let mut request_line = String::new();
+        stream.read_line(&mut request_line).await.unwrap();
+        assert_eq!(request_line, "POST /events HTTP/1.1\r\n");
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            stream.read_line(&mut header).await.unwrap();
+            if header == "\r\n" {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length: ") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).await.unwrap();
+        stream
+            .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+            .await
+            .unwrap();
+
+        let event = serde_json::from_slice::<Value>(&body).unwrap();
+        assert_eq!(event["event"], expected);
+        assert_eq!(event["pid"], 42);
+    }
+}
+
+#[tokio::test]
+async fn nats_subject_receives_lifecycle_events() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = format!("nats://{}/git.sessions", listener.local_addr().unwrap());
+    let target = target.parse::<Target>().unwrap();
+    run_session(EventQueue::spawn(vec![target.into_emitter()]), None);
+
+    for expected in ["session-started", "session-failed"] {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read_stream, mut write_stream) = stream.into_split();
+        write_stream
+            .write_all(b"INFO {\"server_id\":\"test\"}\r\n")
+            .await
+            .unwrap();
+        let mut lines = BufReader::new(read_stream).lines();
+        let connect = lines.next_line().await.unwrap().unwrap();
+        assert!(connect.starts_with("CONNECT {"));
+        let publish = lines.next_line().await.unwrap().unwrap();
+        assert!(publish.starts_with("PUB git.sessions "));
+        let payload = lines.next_line().await.unwrap().unwrap();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "PING");
+        write_stream.write_all(b"PONG\r\n").await.unwrap();
+
+        let event = serde_json::from_str::<Value>(&payload).unwrap();
+        assert_eq!(event["event"], expected);
+    }
+}
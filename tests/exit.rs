@@ -0,0 +1,28 @@
+use std::process::ExitCode;
+
+use git_remote_utils::{exit, protocol::Exit};
+
+fn code(exit: Exit) -> ExitCode {
+    exit::remote_code(&exit)
+}
+
+#[test]
+fn remote_exit_is_mirrored() {
+    assert_eq!(code(Exit::Code(0)), ExitCode::from(0));
+    assert_eq!(code(Exit::Code(128)), ExitCode::from(128));
+    assert_eq!(code(Exit::Signal(9)), ExitCode::from(137));
+}
+
+#[test]
+fn out_of_range_remote_exit_is_a_failure() {
+    for exit in [
+        Exit::Code(-1),
+        Exit::Code(256),
+        Exit::Signal(128),
+        Exit::Signal(-200),
+        Exit::Signal(i32::MAX),
+        Exit::Signal(i32::MIN),
+    ] {
+        assert_eq!(code(exit.clone()), ExitCode::from(1), "{exit:?}");
+    }
+}
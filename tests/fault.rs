@@ -0,0 +1,29 @@
+use git_remote_utils::fault::{Faults, OutputFault};
+
+#[test]
+fn faults_are_parsed() {
+    let faults = "reject-every=3,stall-after=100".parse::<Faults>().unwrap();
+    assert_eq!(faults.reject_every.map(|n| n.get()), Some(3));
+    assert_eq!(faults.stall_after, Some(100));
+    assert_eq!(faults.abort_after, None);
+    assert!("".parse::<Faults>().unwrap().is_empty());
+    assert!("reject-every=0".parse::<Faults>().is_err());
+    assert!("abort-after".parse::<Faults>().is_err());
+    assert!("drop-packets=1".parse::<Faults>().is_err());
+}
+
+#[test]
+fn every_nth_connection_is_rejected() {
+    let faults = "reject-every=3".parse::<Faults>().unwrap();
+    let rejected = (0..9).filter(|&i| faults.rejects(i)).collect::<Vec<_>>();
+    assert_eq!(rejected, [2, 5, 8]);
+    assert!(!Faults::default().rejects(0));
+}
+
+#[test]
+fn abort_wins_over_stall() {
+    let faults = "stall-after=10,abort-after=20".parse::<Faults>().unwrap();
+    assert_eq!(faults.output_fault(9), None);
+    assert_eq!(faults.output_fault(10), Some(OutputFault::Stall));
+    assert_eq!(faults.output_fault(25), Some(OutputFault::Abort));
+}
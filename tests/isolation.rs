@@ -0,0 +1,62 @@
+use std::process::Stdio;
+
+use git_remote_utils::isolation::{ChildLimits, IoPriority};
+use tokio::process::Command;
+
+#[test]
+fn io_priorities_are_parsed() {
+    for (value, priority) in [
+        ("idle", IoPriority::Idle),
+        ("best-effort", IoPriority::BestEffort(4)),
+        ("best-effort:0", IoPriority::BestEffort(0)),
+        ("realtime", IoPriority::Realtime(4)),
+        ("realtime:7", IoPriority::Realtime(7)),
+    ] {
+        assert_eq!(value.parse::<IoPriority>().unwrap(), priority, "{value:?}");
+    }
+}
+
+#[test]
+fn invalid_io_priorities_are_rejected() {
+    for value in [
+        "",
+        "Idle",
+        "idle:0",
+        "best-effort:",
+        "best-effort:8",
+        "best-effort:-1",
+        "realtime:high",
+        "batch",
+        "best-effort:4:4",
+    ] {
+        assert!(value.parse::<IoPriority>().is_err(), "{value:?}");
+    }
+    assert_eq!(
+        "idle:3".parse::<IoPriority>().unwrap_err(),
+        "the idle class has no level"
+    );
+}
+
+async fn limits_seen_by_shell(limits: &ChildLimits) -> (String, i32) {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "ulimit -v; nice"]).stdout(Stdio::piped());
+    limits.apply(&mut cmd);
+    let output = cmd.output().await.unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let (address_space, nice) = stdout.trim_end().split_once('\n').unwrap();
+    (address_space.into(), nice.parse().unwrap())
+}
+
+#[tokio::test]
+async fn limits_are_applied_to_spawned_processes() {
+    let (_, base_nice) = limits_seen_by_shell(&ChildLimits::default()).await;
+
+    let mut limits = ChildLimits::default();
+    limits.nice = Some(5);
+    limits.address_space = Some(1 << 30);
+    let (address_space, nice) = limits_seen_by_shell(&limits).await;
+    // `ulimit -v` reports KiB
+    assert_eq!(address_space, (1 << 20).to_string());
+    assert_eq!(nice, (base_nice + 5).min(19));
+}
@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use git_remote_utils::metrics::{self, Leaks, Metrics, PushGateway, Sink, Snapshot, Statsd};
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::{TcpListener, UdpSocket},
+};
+
+#[test]
+fn tasks_are_counted_until_dropped() {
+    let metrics = Arc::new(Metrics::default());
+    let first = metrics.track_task();
+    let second = metrics.track_task();
+    assert_eq!(metrics.tasks.get(), 2);
+    drop(first);
+    drop(second);
+    assert_eq!(metrics.tasks.get(), 0);
+}
+
+#[test]
+fn leaks_are_resources_held_beyond_idle() {
+    let metrics = Arc::new(Metrics::default());
+    let baseline_fds = metrics::open_fds().unwrap();
+    metrics.open_fds.set(baseline_fds);
+    assert!(Leaks::find(&metrics, baseline_fds).is_empty());
+
+    let _task = metrics.track_task();
+    metrics.open_fds.set(baseline_fds + 2);
+    assert_eq!(
+        Leaks::find(&metrics, baseline_fds),
+        Leaks {
+            tasks: 1,
+            fds: 2,
+            ..Leaks::default()
+        }
+    );
+}
+
+fn snapshot(accepted: u64, active: i64) -> Snapshot {
+    Snapshot {
+        counters: vec![("sessions.accepted", accepted)],
+        gauges: vec![("sessions.active", active)],
+    }
+}
+
+async fn receive(socket: &UdpSocket) -> String {
+    let mut packet = vec![0; 65536];
+    let len = socket.recv(&mut packet).await.unwrap();
+    String::from_utf8(packet[..len].to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn statsd_counters_are_sent_as_increments() {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap().to_string();
+    let mut statsd = Statsd::connect(&addr, "gru").await.unwrap();
+
+    statsd.send(&snapshot(3, 1)).await.unwrap();
+    assert_eq!(
+        receive(&server).await,
+        "gru.sessions.accepted:3|c\ngru.sessions.active:1|g\n"
+    );
+    statsd.send(&snapshot(3, 0)).await.unwrap();
+    assert_eq!(receive(&server).await, "gru.sessions.active:0|g\n");
+    statsd.send(&snapshot(5, 0)).await.unwrap();
+    assert_eq!(
+        receive(&server).await,
+        "gru.sessions.accepted:2|c\ngru.sessions.active:0|g\n"
+    );
+}
+
+#[tokio::test]
+async fn statsd_increments_are_kept_until_sent() {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap().to_string();
+    let mut statsd = Statsd::connect(&addr, "gru").await.unwrap();
+
+    // too large for a datagram
+    let oversized = Snapshot {
+        gauges: vec![(Box::leak("x".repeat(70_000).into_boxed_str()), 0)],
+        ..snapshot(2, 0)
+    };
+    assert!(statsd.send(&oversized).await.is_err());
+    statsd.send(&snapshot(3, 0)).await.unwrap();
+    assert_eq!(
+        receive(&server).await,
+        "gru.sessions.accepted:3|c\ngru.sessions.active:0|g\n"
+    );
+}
+
+#[test]
+fn push_gateway_urls_are_parsed() {
+    let gateway = "http://localhost".parse::<PushGateway>().unwrap();
+    assert_eq!(gateway.authority(), "localhost:9091");
+    let gateway = "http://[::1]:8080/prefix/".parse::<PushGateway>().unwrap();
+    assert_eq!(gateway.authority(), "[::1]:8080");
+    for url in ["localhost:9091", "https://localhost", "http:///metrics"] {
+        assert!(url.parse::<PushGateway>().is_err(), "{url}");
+    }
+}
+
+/// Answers a single request with `status`, returning the request.
+async fn http_server(status: &'static str) -> (String, tokio::task::JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = vec![0; 4096];
+        let mut len = 0;
+        // the last metric ends the body
+        while !String::from_utf8_lossy(&request[..len]).contains("budget_used_bytes 0\n") {
+            let n = stream.read(&mut request[len..]).await.unwrap();
+            assert_ne!(n, 0, "request was cut short");
+            len += n;
+        }
+        stream
+            .write_all(format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n").as_bytes())
+            .await
+            .unwrap();
+        String::from_utf8(request[..len].to_vec()).unwrap()
+    });
+    (format!("http://{addr}/base"), server)
+}
+
+#[tokio::test]
+async fn push_gateway_receives_the_totals() {
+    let metrics = Metrics::default();
+    metrics.accepted.inc();
+    metrics.accepted.inc();
+    metrics.active.inc();
+
+    let (url, server) = http_server("200 OK").await;
+    let mut gateway = url.parse::<PushGateway>().unwrap().prefix("git-gru");
+    gateway.send(&metrics.snapshot()).await.unwrap();
+    let request = server.await.unwrap();
+    assert!(
+        request.starts_with("PUT /base/metrics/job/git-gru HTTP/1.1\r\n"),
+        "{request}"
+    );
+    let (_, body) = request.split_once("\r\n\r\n").unwrap();
+    assert!(
+        body.contains("# TYPE git_gru_sessions_accepted counter\ngit_gru_sessions_accepted 2\n"),
+        "{body}"
+    );
+    assert!(
+        body.contains("# TYPE git_gru_sessions_active gauge\ngit_gru_sessions_active 1\n"),
+        "{body}"
+    );
+
+    let (url, server) = http_server("500 Internal Server Error").await;
+    let mut gateway = url.parse::<PushGateway>().unwrap();
+    let e = gateway.send(&metrics.snapshot()).await.unwrap_err();
+    assert!(e.to_string().contains("500 Internal Server Error"), "{e}");
+    server.await.unwrap();
+}
@@ -0,0 +1,32 @@
+use std::fs;
+
+use git_remote_utils::pidfile::{PidFile, PidFileError};
+
+#[test]
+fn pidfile_is_held_until_dropped() {
+    let path = std::env::temp_dir().join(format!("gru-{}-held.pid", std::process::id()));
+    let pid = std::process::id();
+
+    let pidfile = PidFile::create(&path).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), format!("{pid}\n"));
+    assert!(matches!(
+        PidFile::create(&path),
+        Err(PidFileError::Running { pid: Some(running) }) if running == pid
+    ));
+
+    drop(pidfile);
+    assert!(!path.exists());
+}
+
+#[test]
+fn stale_pidfile_is_taken_over() {
+    let path = std::env::temp_dir().join(format!("gru-{}-stale.pid", std::process::id()));
+    fs::write(&path, "4194305\nleftover\n").unwrap();
+
+    let pidfile = PidFile::create(&path).unwrap();
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        format!("{}\n", std::process::id())
+    );
+    drop(pidfile);
+}
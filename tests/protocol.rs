@@ -0,0 +1,233 @@
+use std::{pin::pin, sync::Arc};
+
+use bytes::{BufMut as _, BytesMut};
+use git_remote_utils::protocol::{
+    self, AuthResponse, ClientHello, ClientMessage, Command, HelloReply, OutputRequest, Priority,
+    ServerHello, ServerMessage, SessionHello, SpawnMessage, HELLO_MAGIC, MAX_BUFFER_SIZE,
+    MAX_FRAME_LENGTH, MAX_HANDSHAKE_FRAME_LENGTH, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+    SESSION_ID_FEATURE,
+};
+use proptest::prelude::*;
+use tokio_serde::{formats::MessagePack, Serializer as _};
+use tokio_util::codec::Decoder as _;
+use ulid::Ulid;
+
+fn hello() -> ClientHello {
+    ClientHello::new(Command::Store, Some("https://example.com/repo.git".into()))
+}
+
+#[test]
+fn hello_round_trips() {
+    let hello = hello();
+    let frame = BytesMut::from(&protocol::encode_hello(&hello).unwrap()[..]);
+    let decoded = protocol::decode_hello::<ClientHello>(&frame)
+        .unwrap()
+        .unwrap();
+    assert_eq!(decoded.command, hello.command);
+    assert_eq!(decoded.url, hello.url);
+    assert_eq!(decoded.features, hello.features);
+
+    let reply: HelloReply = hello.negotiate();
+    let frame = BytesMut::from(&protocol::encode_hello(&reply).unwrap()[..]);
+    let decoded = protocol::decode_hello::<HelloReply>(&frame)
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(decoded.protocol_version, PROTOCOL_VERSION);
+}
+
+#[test]
+fn session_id_is_sent_when_negotiated() {
+    let reply = hello().negotiate().unwrap();
+    assert!(reply.has_feature(SESSION_ID_FEATURE));
+
+    let session_id = Ulid::new();
+    let frame = protocol::encode_hello(&SessionHello { session_id }).unwrap();
+    let frame = BytesMut::from(&frame[..]);
+    let decoded = protocol::decode_hello::<SessionHello>(&frame)
+        .unwrap()
+        .unwrap();
+    assert_eq!(decoded.session_id, session_id);
+}
+
+#[test]
+fn spawn_message_is_not_a_hello() {
+    for command in [Command::Get, Command::Store, Command::Erase] {
+        for priority in [Priority::Interactive, Priority::Batch] {
+            let msg = SpawnMessage {
+                command,
+                priority: Some(priority),
+            };
+            let frame = pin!(MessagePack::<(), SpawnMessage>::default())
+                .serialize(&msg)
+                .unwrap();
+            let frame = BytesMut::from(&frame[..]);
+            assert!(protocol::decode_hello::<ClientHello>(&frame).is_none());
+            let decoded = protocol::decode::<SpawnMessage>(&frame).unwrap();
+            assert_eq!(decoded.command, command);
+            assert_eq!(decoded.priority, Some(priority));
+        }
+    }
+}
+
+#[test]
+fn priority_is_left_out_unless_negotiated() {
+    let msg = SpawnMessage {
+        command: Command::Get,
+        priority: None,
+    };
+    let frame = pin!(MessagePack::<(), SpawnMessage>::default())
+        .serialize(&msg)
+        .unwrap();
+    let with_priority = pin!(MessagePack::<(), SpawnMessage>::default())
+        .serialize(&SpawnMessage {
+            priority: Some(Priority::Batch),
+            ..msg
+        })
+        .unwrap();
+    assert!(frame.len() < with_priority.len());
+    let decoded = protocol::decode::<SpawnMessage>(&BytesMut::from(&frame[..])).unwrap();
+    assert_eq!(decoded.priority, None);
+}
+
+#[test]
+fn newer_client_is_downgraded() {
+    let hello = ClientHello {
+        protocol_version: PROTOCOL_VERSION + 1,
+        features: vec!["priority".into(), "from-the-future".into()],
+        ..hello()
+    };
+    let reply = hello.negotiate().unwrap();
+    assert_eq!(reply.protocol_version, PROTOCOL_VERSION);
+    assert_eq!(reply.features, ["priority"]);
+    assert!(reply.validate().is_ok());
+}
+
+#[test]
+fn older_client_keeps_its_version_and_features() {
+    let hello = ClientHello {
+        protocol_version: MIN_PROTOCOL_VERSION,
+        features: vec![],
+        ..hello()
+    };
+    let reply = hello.negotiate().unwrap();
+    assert_eq!(reply.protocol_version, MIN_PROTOCOL_VERSION);
+    assert!(reply.features.is_empty());
+    assert!(!reply.has_feature("priority"));
+}
+
+#[test]
+fn unsupported_client_is_rejected() {
+    let hello = ClientHello {
+        protocol_version: MIN_PROTOCOL_VERSION - 1,
+        ..hello()
+    };
+    assert!(hello.negotiate().is_err());
+}
+
+#[test]
+fn client_rejects_unexpected_server_choices() {
+    let reply = ServerHello {
+        protocol_version: PROTOCOL_VERSION + 1,
+        server_version: "99.0.0".into(),
+        features: vec![],
+    };
+    assert!(reply.validate().is_err());
+
+    let reply = ServerHello {
+        protocol_version: PROTOCOL_VERSION,
+        server_version: "99.0.0".into(),
+        features: vec!["from-the-future".into()],
+    };
+    assert!(reply.validate().is_err());
+}
+
+#[test]
+fn oversized_frame_is_rejected_before_it_is_buffered() {
+    let mut codec = protocol::codec(MAX_HANDSHAKE_FRAME_LENGTH);
+    let mut buf = BytesMut::new();
+    buf.put_u32(u32::MAX);
+    buf.put_slice(b"GRU\0");
+    let capacity = buf.capacity();
+    assert!(codec.decode(&mut buf).is_err());
+    assert_eq!(buf.capacity(), capacity);
+
+    let mut buf = BytesMut::new();
+    buf.put_u32(MAX_HANDSHAKE_FRAME_LENGTH as u32);
+    buf.put_bytes(0, MAX_HANDSHAKE_FRAME_LENGTH);
+    assert!(codec.decode(&mut buf).unwrap().is_some());
+}
+
+/// Malformed frames a hostile peer could send, claiming huge lengths or cut short.
+const MALFORMED: &[&[u8]] = &[
+    // array32, str32 and bin32 of 4 GiB without their content
+    b"\xdd\xff\xff\xff\xff",
+    b"\xdb\xff\xff\xff\xff",
+    b"\xc6\xff\xff\xff\xff",
+    // map32 of 4 GiB entries
+    b"\xdf\xff\xff\xff\xff",
+    // a variant holding a bin32 of 4 GiB
+    b"\x81\x00\x81\x00\xc6\xff\xff\xff\xff",
+    b"\x81\x02\x81\x00\xc6\xff\xff\xff\xff",
+    // a hello with 4 GiB features
+    b"\x95\x01\xa50.1.0\x81\x00\xc0\xc0\xdd\xff\xff\xff\xff",
+    // truncated, and reserved marker
+    b"\x92",
+    b"\xc1",
+    b"",
+];
+
+#[test]
+fn largest_chunks_fit_in_a_frame() {
+    let mut chunk = BytesMut::new();
+    chunk.resize(MAX_BUFFER_SIZE, 0xff);
+    let chunk = Arc::new(chunk);
+
+    let msg = ServerMessage::Stderr(OutputRequest::Output(Arc::clone(&chunk)));
+    let frame = pin!(MessagePack::<(), ServerMessage>::default())
+        .serialize(&msg)
+        .unwrap();
+    assert!(frame.len() <= MAX_FRAME_LENGTH, "{}", frame.len());
+
+    let msg = ClientMessage::Stdin(OutputRequest::Output(chunk));
+    let frame = pin!(MessagePack::<(), ClientMessage>::default())
+        .serialize(&msg)
+        .unwrap();
+    assert!(frame.len() <= MAX_FRAME_LENGTH, "{}", frame.len());
+}
+
+#[test]
+fn malformed_frames_are_errors() {
+    for &input in MALFORMED {
+        let frame = BytesMut::from(input);
+        assert!(protocol::decode::<SpawnMessage>(&frame).is_err());
+        assert!(protocol::decode::<ClientMessage>(&frame).is_err());
+        assert!(protocol::decode::<ServerMessage>(&frame).is_err());
+
+        let mut hello = BytesMut::from(&HELLO_MAGIC[..]);
+        hello.put_slice(input);
+        assert!(protocol::decode_hello::<ClientHello>(&hello)
+            .unwrap()
+            .is_err());
+        assert!(protocol::decode_hello::<AuthResponse>(&hello)
+            .unwrap()
+            .is_err());
+    }
+}
+
+proptest! {
+    #[test]
+    fn arbitrary_frames_are_decoded_without_panicking(input in prop::collection::vec(any::<u8>(), 0..512)) {
+        let frame = BytesMut::from(&input[..]);
+        let _ = protocol::decode::<SpawnMessage>(&frame);
+        let _ = protocol::decode::<ClientMessage>(&frame);
+        let _ = protocol::decode::<ServerMessage>(&frame);
+
+        let mut hello = BytesMut::from(&HELLO_MAGIC[..]);
+        hello.put_slice(&input);
+        let _ = protocol::decode_hello::<ClientHello>(&hello);
+        let _ = protocol::decode_hello::<HelloReply>(&hello);
+        let _ = protocol::decode_hello::<SessionHello>(&hello);
+        let _ = protocol::decode_hello::<AuthResponse>(&hello);
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::BytesMut;
+use git_remote_utils::{task, thread};
+use proptest::prelude::*;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    runtime,
+    sync::mpsc,
+};
+
+/// Reader returning the given chunks one by one, optionally failing after them.
+#[derive(Debug)]
+struct ChunkedReader {
+    chunks: VecDeque<Vec<u8>>,
+    interrupt: bool,
+    fail: bool,
+}
+
+impl ChunkedReader {
+    fn new(chunks: Vec<Vec<u8>>, interrupt: bool, fail: bool) -> Self {
+        Self {
+            chunks: chunks.into_iter().filter(|c| !c.is_empty()).collect(),
+            interrupt,
+            fail,
+        }
+    }
+
+    fn read_chunk(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.interrupt {
+            self.interrupt = false;
+            return Err(io::ErrorKind::Interrupted.into());
+        }
+        let mut chunk = match self.chunks.pop_front() {
+            Some(chunk) => chunk,
+            None if self.fail => return Err(io::Error::other("injected read error")),
+            None => return Ok(0),
+        };
+        let len = chunk.len().min(buf.len());
+        buf[..len].copy_from_slice(&chunk[..len]);
+        if len < chunk.len() {
+            self.chunks.push_front(chunk.split_off(len));
+        }
+        // interrupt every other read to exercise the retry path
+        self.interrupt = !self.chunks.is_empty() && len % 2 == 0;
+        Ok(len)
+    }
+}
+
+impl Read for ChunkedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_chunk(buf)
+    }
+}
+
+impl AsyncRead for ChunkedReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let len = self.get_mut().read_chunk(buf.initialize_unfilled())?;
+        buf.advance(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Writer accepting at most `limit` bytes before failing.
+#[derive(Debug, Default)]
+struct LimitedWriter {
+    written: Vec<u8>,
+    limit: Option<usize>,
+}
+
+impl LimitedWriter {
+    fn write_some(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = match self.limit {
+            Some(limit) if self.written.len() >= limit => {
+                return Err(io::Error::other("injected write error"))
+            }
+            Some(limit) => buf.len().min(limit - self.written.len()),
+            None => buf.len(),
+        };
+        self.written.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+}
+
+impl Write for LimitedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_some(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncWrite for LimitedWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(self.get_mut().write_some(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn chunks() -> impl Strategy<Value = Vec<Vec<u8>>> {
+    prop::collection::vec(prop::collection::vec(any::<u8>(), 0..64), 0..32)
+}
+
+fn runtime() -> runtime::Runtime {
+    runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+/// Runs `task::input`, acknowledging every chunk, and returns the received bytes.
+fn run_task_input(reader: ChunkedReader, buffer_size: usize) -> (Vec<u8>, Result<(), String>) {
+    runtime().block_on(async move {
+        let (bytes_tx, mut bytes_rx) = mpsc::channel::<Arc<BytesMut>>(1);
+        let (res_tx, res_rx) = mpsc::channel(1);
+        let input = tokio::spawn(task::input(reader, buffer_size, bytes_tx, res_rx));
+        let mut received = Vec::new();
+        while let Some(bytes) = bytes_rx.recv().await {
+            assert!(!bytes.is_empty() && bytes.len() <= buffer_size);
+            received.extend_from_slice(&bytes);
+            drop(bytes);
+            res_tx.send(Ok(())).await.unwrap();
+        }
+        let res = input.await.unwrap().map_err(|e| e.to_string());
+        (received, res)
+    })
+}
+
+/// Feeds `chunks` to `task::output` and returns the written bytes and the acknowledgements.
+fn run_task_output(
+    writer: LimitedWriter,
+    chunks: &[Vec<u8>],
+) -> (Vec<u8>, Vec<Result<(), String>>) {
+    let chunks = chunks.to_vec();
+    runtime().block_on(async move {
+        let (bytes_tx, bytes_rx) = mpsc::channel(1);
+        let (res_tx, mut res_rx) = mpsc::channel(1);
+        let output = tokio::spawn(async move {
+            let mut writer = writer;
+            task::output(&mut writer, res_tx, bytes_rx).await.unwrap();
+            writer.written
+        });
+        let mut acks = Vec::new();
+        for chunk in chunks {
+            bytes_tx
+                .send(Arc::new(BytesMut::from(&chunk[..])))
+                .await
+                .unwrap();
+            acks.push(res_rx.recv().await.unwrap());
+        }
+        drop(bytes_tx);
+        (output.await.unwrap(), acks)
+    })
+}
+
+proptest! {
+    #[test]
+    fn task_input_preserves_bytes(chunks in chunks(), buffer_size in 1usize..128) {
+        let expected = chunks.concat();
+        let (received, res) = run_task_input(ChunkedReader::new(chunks, true, false), buffer_size);
+        prop_assert_eq!(res, Ok(()));
+        prop_assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn task_input_propagates_read_error(chunks in chunks(), buffer_size in 1usize..128) {
+        let expected = chunks.concat();
+        let (received, res) = run_task_input(ChunkedReader::new(chunks, false, true), buffer_size);
+        prop_assert!(res.is_err());
+        prop_assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn task_input_propagates_remote_error(chunks in chunks(), buffer_size in 1usize..128) {
+        prop_assume!(chunks.iter().any(|c| !c.is_empty()));
+        let res = runtime().block_on(async move {
+            let reader = ChunkedReader::new(chunks, false, false);
+            let (bytes_tx, mut bytes_rx) = mpsc::channel(1);
+            let (res_tx, res_rx) = mpsc::channel(1);
+            let input = tokio::spawn(task::input(reader, buffer_size, bytes_tx, res_rx));
+            let _bytes = bytes_rx.recv().await.unwrap();
+            res_tx.send(Err("remote error".to_owned())).await.unwrap();
+            input.await.unwrap()
+        });
+        prop_assert_eq!(res.unwrap_err().to_string(), "remote error");
+    }
+
+    #[test]
+    fn task_output_preserves_bytes(chunks in chunks()) {
+        let (written, acks) = run_task_output(LimitedWriter::default(), &chunks);
+        prop_assert!(acks.iter().all(|ack| ack.is_ok()));
+        prop_assert_eq!(written, chunks.concat());
+    }
+
+    #[test]
+    fn task_output_reports_write_error(chunks in chunks(), limit in 0usize..256) {
+        let writer = LimitedWriter { written: Vec::new(), limit: Some(limit) };
+        let (written, acks) = run_task_output(writer, &chunks);
+        let mut total = 0;
+        for (chunk, ack) in chunks.iter().zip(&acks) {
+            total += chunk.len();
+            // a chunk is acknowledged successfully only if it was written entirely
+            prop_assert_eq!(ack.is_ok(), chunk.is_empty() || total <= limit);
+        }
+        let all = chunks.concat();
+        prop_assert_eq!(&written[..], &all[..written.len()]);
+    }
+
+    #[test]
+    fn thread_pumps_preserve_bytes(chunks in chunks(), buffer_size in 1usize..128) {
+        let expected = chunks.concat();
+        let (bytes_tx, bytes_rx) = mpsc::channel(1);
+        let (res_tx, res_rx) = mpsc::channel(1);
+        let reader = ChunkedReader::new(chunks, true, false);
+        let input = std::thread::spawn(move || thread::input(reader, buffer_size, bytes_tx, res_rx));
+        let output = std::thread::spawn(move || {
+            let mut writer = LimitedWriter::default();
+            thread::output(&mut writer, res_tx, bytes_rx).map(|()| writer.written)
+        });
+        prop_assert!(input.join().unwrap().is_ok());
+        prop_assert_eq!(output.join().unwrap().unwrap(), expected);
+    }
+}
@@ -0,0 +1,41 @@
+use git_remote_utils::sanitize::{self, Sanitizer};
+use proptest::prelude::*;
+
+fn filter(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    Sanitizer::default().filter(input, &mut output);
+    output
+}
+
+#[test]
+fn escape_sequences_are_removed() {
+    assert_eq!(
+        filter(b"\x1b]0;title\x07\x1b[2J\x1b[31mred\x1b[0m\x1b]52;c;Zm9v\x1b\\ text\n"),
+        b"red text\n"
+    );
+    assert_eq!(filter(b"a\x08\x7fb\x1b(Bc\r\n"), b"abc\r\n");
+    // C1 controls encoded in UTF-8, next to other characters of the same lead byte
+    assert_eq!(
+        filter("\u{9b}2Jx\u{a0}é\u{9d}8;;http://x\u{9c}\x07y".as_bytes()),
+        "x\u{a0}éy".as_bytes()
+    );
+    assert_eq!(
+        sanitize::text("rejected: \x1b[8mhidden\x1b[0m"),
+        "rejected: hidden"
+    );
+}
+
+proptest! {
+    #[test]
+    fn chunks_are_filtered_like_the_whole(input in prop::collection::vec(any::<u8>(), 0..256), split in any::<prop::sample::Index>()) {
+        let split = split.index(input.len() + 1);
+        let mut sanitizer = Sanitizer::default();
+        let mut output = Vec::new();
+        sanitizer.filter(&input[..split], &mut output);
+        sanitizer.filter(&input[split..], &mut output);
+        prop_assert_eq!(&output, &filter(&input));
+        prop_assert!(output
+            .iter()
+            .all(|&b| !(b < 0x20 || b == 0x7f) || matches!(b, b'\n' | b'\r' | b'\t')));
+    }
+}
@@ -0,0 +1,369 @@
+use std::{
+    fs,
+    io::{BufRead as _, BufReader, Read as _},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use git_remote_utils::audit;
+
+/// Server running `command` for `get`, listening on a socket unique to the test.
+struct Server {
+    child: Child,
+    socket: PathBuf,
+}
+
+impl Server {
+    fn start(name: &str, command: &str) -> Self {
+        Self::with_args(name, command, &[])
+    }
+
+    fn with_args(name: &str, command: &str, args: &[&str]) -> Self {
+        let socket =
+            std::env::temp_dir().join(format!("gru-session-{}-{name}.sock", std::process::id()));
+        let _ = fs::remove_file(&socket);
+        let child = Command::new(env!("CARGO_BIN_EXE_gru-credential-helper-server"))
+            .arg("--bind")
+            .arg(format!("unix:{}", socket.display()))
+            .arg(format!("--command=get={command}"))
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        assert!(
+            wait_until(|| socket.exists()),
+            "server did not create {}",
+            socket.display()
+        );
+        Self { child, socket }
+    }
+
+    /// Spawns a client running `get`, with all its stdio piped.
+    fn client(&self) -> Child {
+        let mut client = Command::new(env!("CARGO_BIN_EXE_gru-credential-helper-client"))
+            .arg("--connect")
+            .arg(format!("unix:{}", self.socket.display()))
+            .arg("get")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        drop(client.stdin.take());
+        client
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_file(&self.socket);
+        let _ = fs::remove_file(self.socket.with_extension("sock.lock"));
+    }
+}
+
+fn wait_until(mut f: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        if f() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    false
+}
+
+fn wait_exit(client: &mut Child) -> ExitStatus {
+    let mut status = None;
+    assert!(
+        wait_until(|| {
+            status = client.try_wait().unwrap();
+            status.is_some()
+        }),
+        "client did not exit"
+    );
+    status.unwrap()
+}
+
+fn read_stderr(client: &mut Child) -> String {
+    let mut stderr = String::new();
+    client
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut stderr)
+        .unwrap();
+    stderr
+}
+
+#[test]
+fn closed_stdout_aborts_the_session() {
+    let server = Server::start("stdout", "yes");
+    let mut client = server.client();
+    let mut stdout = client.stdout.take().unwrap();
+    stdout.read_exact(&mut [0; 16]).unwrap();
+    drop(stdout);
+
+    let status = wait_exit(&mut client);
+    let stderr = read_stderr(&mut client);
+    assert_eq!(status.code(), Some(74), "{stderr}");
+    assert!(stderr.contains("stdout was closed"), "{stderr}");
+    assert!(!stderr.contains("panicked"), "{stderr}");
+}
+
+#[test]
+fn closed_stderr_keeps_the_session() {
+    // the command outlives the stderr pipe closed by the server
+    let server = Server::start(
+        "stderr",
+        "trap '' PIPE; yes | head -c 100000 >&2; echo done",
+    );
+    let mut client = server.client();
+    drop(client.stderr.take());
+
+    let mut stdout = String::new();
+    client
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut stdout)
+        .unwrap();
+    assert_eq!(stdout, "done\n");
+    assert!(wait_exit(&mut client).success());
+}
+
+#[test]
+fn stderr_of_control_sequences_only_is_acknowledged() {
+    // the chunk is filtered out entirely, and must not leave the server waiting for its ack
+    let server = Server::start("escape", r"printf '\033[0m' >&2; echo done");
+    let mut client = server.client();
+
+    let status = wait_exit(&mut client);
+    let mut stdout = String::new();
+    client
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut stdout)
+        .unwrap();
+    let stderr = read_stderr(&mut client);
+    assert!(status.success(), "{stderr}");
+    assert_eq!(stdout, "done\n");
+    assert!(!stderr.contains('\x1b'), "{stderr:?}");
+}
+
+#[test]
+fn command_is_killed_when_the_client_goes_away() {
+    let server = Server::start("gone", "echo $$; exec sleep 30");
+    let mut client = server.client();
+    let mut pid = String::new();
+    BufReader::new(client.stdout.take().unwrap())
+        .read_line(&mut pid)
+        .unwrap();
+    let proc = Path::new("/proc").join(pid.trim());
+    assert!(proc.exists());
+
+    client.kill().unwrap();
+    client.wait().unwrap();
+    assert!(
+        wait_until(|| !proc.exists()),
+        "command {} was not reaped",
+        pid.trim()
+    );
+}
+
+#[test]
+fn daemonized_server_is_ready_once_started() {
+    let socket =
+        std::env::temp_dir().join(format!("gru-session-{}-daemon.sock", std::process::id()));
+    let pidfile = socket.with_extension("pid");
+    let _ = fs::remove_file(&socket);
+    let status = Command::new(env!("CARGO_BIN_EXE_gru-credential-helper-server"))
+        .arg("--bind")
+        .arg(format!("unix:{}", socket.display()))
+        .arg("--daemonize")
+        .arg("--pidfile")
+        .arg(&pidfile)
+        .arg("--command=get=echo done")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let pid = fs::read_to_string(&pidfile).unwrap();
+    let proc = Path::new("/proc").join(pid.trim());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_gru-credential-helper-client"))
+        .arg("--connect")
+        .arg(format!("unix:{}", socket.display()))
+        .arg("get")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert_eq!(output.stdout, b"done\n");
+    assert!(output.status.success());
+
+    Command::new("kill").arg(pid.trim()).status().unwrap();
+    assert!(
+        wait_until(|| !proc.exists()),
+        "server {} is running",
+        pid.trim()
+    );
+    for path in [&socket, &socket.with_extension("sock.lock"), &pidfile] {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[test]
+fn upgrade_hands_the_socket_over() {
+    let path = |name: &str| {
+        std::env::temp_dir().join(format!("gru-session-{}-upgrade.{name}", std::process::id()))
+    };
+    let (pidfile, started, hold) = (path("pid"), path("started"), path("hold"));
+    // sessions run until `hold` is removed
+    let mut server = Server::with_args(
+        "upgrade",
+        &format!(
+            "touch {}; while [ -e {} ]; do sleep 0.05; done; echo $PPID",
+            started.display(),
+            hold.display()
+        ),
+        &["--pidfile", pidfile.to_str().unwrap()],
+    );
+    let old_pid = server.child.id().to_string();
+    let get = |client: Child| {
+        let output = client.wait_with_output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap().trim().to_owned()
+    };
+    assert_eq!(get(server.client()), old_pid);
+
+    fs::remove_file(&started).unwrap();
+    fs::write(&hold, "").unwrap();
+    let running = server.client();
+    assert!(wait_until(|| started.exists()));
+    Command::new("kill")
+        .args(["-USR2", &old_pid])
+        .status()
+        .unwrap();
+    let mut new_pid = String::new();
+    assert!(wait_until(|| {
+        new_pid = fs::read_to_string(&pidfile).unwrap_or_default();
+        !new_pid.is_empty() && new_pid.trim() != old_pid
+    }));
+    let new_pid = new_pid.trim().to_owned();
+
+    // the old server exits once its session is done
+    thread::sleep(Duration::from_millis(300));
+    assert!(server.child.try_wait().unwrap().is_none());
+    fs::remove_file(&hold).unwrap();
+    assert_eq!(get(running), old_pid);
+    assert!(wait_exit(&mut server.child).success());
+    assert_eq!(get(server.client()), new_pid);
+
+    Command::new("kill").arg(&new_pid).status().unwrap();
+    let proc = Path::new("/proc").join(&new_pid);
+    assert!(wait_until(|| !proc.exists()), "server {new_pid} is running");
+    let _ = fs::remove_file(pidfile);
+    let _ = fs::remove_file(started);
+}
+
+#[test]
+fn upgrade_keeps_the_audit_log_chained() {
+    const SESSIONS: usize = 8;
+    let path = |name: &str| {
+        std::env::temp_dir().join(format!("gru-session-{}-audit.{name}", std::process::id()))
+    };
+    let (pidfile, audit_log, started, hold) =
+        (path("pid"), path("log"), path("started"), path("hold"));
+    let _ = fs::remove_file(&audit_log);
+    let _ = fs::remove_dir_all(&started);
+    fs::create_dir(&started).unwrap();
+    fs::write(&hold, "").unwrap();
+    // sessions run until `hold` is removed, so that they end at once in both servers
+    let mut server = Server::with_args(
+        "audit",
+        &format!(
+            "touch {}/$$; while [ -e {} ]; do sleep 0.01; done; echo done",
+            started.display(),
+            hold.display()
+        ),
+        &[
+            "--pidfile",
+            pidfile.to_str().unwrap(),
+            "--audit-log",
+            audit_log.to_str().unwrap(),
+        ],
+    );
+    let old_pid = server.child.id().to_string();
+    let started_sessions = || fs::read_dir(&started).unwrap().count();
+
+    let mut clients = (0..SESSIONS).map(|_| server.client()).collect::<Vec<_>>();
+    assert!(wait_until(|| started_sessions() == SESSIONS));
+    Command::new("kill")
+        .args(["-USR2", &old_pid])
+        .status()
+        .unwrap();
+    let mut new_pid = String::new();
+    assert!(wait_until(|| {
+        new_pid = fs::read_to_string(&pidfile).unwrap_or_default();
+        !new_pid.is_empty() && new_pid.trim() != old_pid
+    }));
+    let new_pid = new_pid.trim().to_owned();
+    clients.extend((0..SESSIONS).map(|_| server.client()));
+    assert!(wait_until(|| started_sessions() == 2 * SESSIONS));
+
+    fs::remove_file(&hold).unwrap();
+    for client in clients {
+        assert!(client.wait_with_output().unwrap().status.success());
+    }
+    assert!(wait_exit(&mut server.child).success());
+    // records are written once the sessions have ended
+    assert!(wait_until(|| {
+        fs::read_to_string(&audit_log).unwrap().lines().count() == 2 * SESSIONS
+    }));
+
+    Command::new("kill").arg(&new_pid).status().unwrap();
+    let proc = Path::new("/proc").join(&new_pid);
+    assert!(wait_until(|| !proc.exists()), "server {new_pid} is running");
+    let log = fs::read(&audit_log).unwrap();
+    let _ = fs::remove_file(pidfile);
+    let _ = fs::remove_file(audit_log);
+    let _ = fs::remove_dir_all(started);
+
+    let verified = audit::verify(&log[..]).unwrap();
+    assert_eq!(verified.records, 2 * SESSIONS);
+}
+
+/// Connects to `server` without sending anything.
+fn idle_connection(server: &Server) -> UnixStream {
+    let stream = UnixStream::connect(&server.socket).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+}
+
+#[test]
+fn connection_without_request_is_closed() {
+    let server = Server::with_args("handshake", "true", &["--handshake-timeout=200ms"]);
+    let mut stream = idle_connection(&server);
+    let start = Instant::now();
+    assert_eq!(stream.read(&mut [0; 16]).unwrap(), 0);
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn pending_connections_are_limited() {
+    let server = Server::with_args("pending", "true", &["--max-pending=1"]);
+    let first = idle_connection(&server);
+    let mut second = idle_connection(&server);
+    // the first connection waits for its request, the second is closed right away
+    assert_eq!(second.read(&mut [0; 16]).unwrap(), 0);
+
+    drop(first);
+    assert!(wait_until(|| server.client().wait().unwrap().success()));
+}
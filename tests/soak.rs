@@ -0,0 +1,71 @@
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt as _,
+    time::{Duration, Instant},
+};
+
+use git_remote_utils::{
+    credential::Description,
+    soak::{self, Percentiles, SoakConfig},
+};
+
+#[test]
+fn percentiles_use_the_nearest_rank() {
+    let mut durations = (1..=200)
+        .rev()
+        .map(Duration::from_millis)
+        .collect::<Vec<_>>();
+    let percentiles = Percentiles::of(&mut durations);
+    assert_eq!(
+        percentiles,
+        Percentiles {
+            p50: 100.0,
+            p90: 180.0,
+            p99: 198.0,
+            max: 200.0,
+        }
+    );
+    assert_eq!(Percentiles::of(&mut []), Percentiles::default());
+}
+
+#[test]
+fn description_is_built_from_the_url() {
+    let url = "https://example.com/group/repo.git";
+    let desc = Description::from_url(url).unwrap();
+    assert_eq!(
+        desc.to_input(),
+        b"protocol=https\nhost=example.com\npath=group/repo.git\n"
+    );
+    assert_eq!(
+        Description::parse(&desc.to_input()).url().as_deref(),
+        Some(url)
+    );
+    assert!(Description::from_url("example.com").is_none());
+}
+
+#[tokio::test]
+async fn hung_clients_are_killed_and_counted() {
+    let client = std::env::temp_dir().join(format!("gru-soak-{}-hung-client", std::process::id()));
+    fs::write(&client, "#!/bin/sh\nexec sleep 60\n").unwrap();
+    fs::set_permissions(&client, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let start = Instant::now();
+    let report = soak::run(&SoakConfig {
+        client: client.clone(),
+        connect_addr: "127.0.0.1:9".into(),
+        operation: "get".into(),
+        input: Vec::new(),
+        clients: 2,
+        duration: Duration::from_millis(300),
+        session_timeout: Duration::from_millis(100),
+        server_pid: None,
+    })
+    .await
+    .unwrap();
+    fs::remove_file(&client).unwrap();
+
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(report.sessions >= 2, "{report:?}");
+    assert_eq!(report.failures, report.sessions);
+    assert_eq!(report.failures_by_exit["timed out"], report.sessions);
+}
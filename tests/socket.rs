@@ -0,0 +1,73 @@
+use std::{
+    fs, io,
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+};
+
+use git_remote_utils::socket::SocketLock;
+
+fn socket_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("gru-socket-{}-{name}.sock", std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+fn remove(path: &Path) {
+    let _ = fs::remove_file(path);
+    fs::remove_file(path.with_extension("sock.lock")).unwrap();
+}
+
+#[test]
+fn stale_socket_is_removed() {
+    let path = socket_path("stale");
+    drop(UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+
+    let lock = SocketLock::acquire(&path).unwrap();
+    assert!(!path.exists());
+    let listener = UnixListener::bind(&path).unwrap();
+    UnixStream::connect(&path).unwrap();
+
+    drop((lock, listener));
+    remove(&path);
+}
+
+#[test]
+fn live_socket_is_kept() {
+    let path = socket_path("live");
+    // a server that doesn't take the lock
+    let _listener = UnixListener::bind(&path).unwrap();
+    let lock = SocketLock::acquire(&path).unwrap();
+    assert!(path.exists());
+    assert_eq!(
+        UnixListener::bind(&path).unwrap_err().kind(),
+        io::ErrorKind::AddrInUse
+    );
+    drop(lock);
+    remove(&path);
+}
+
+#[test]
+fn locked_socket_is_kept() {
+    let path = socket_path("locked");
+    let lock = SocketLock::acquire(&path).unwrap();
+    drop(UnixListener::bind(&path).unwrap());
+
+    let err = SocketLock::acquire(&path).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+    assert!(path.exists());
+
+    drop(lock);
+    drop(SocketLock::acquire(&path).unwrap());
+    assert!(!path.exists());
+    remove(&path);
+}
+
+#[test]
+fn other_files_are_kept() {
+    let path = socket_path("file");
+    fs::write(&path, "data").unwrap();
+    drop(SocketLock::acquire(&path).unwrap());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "data");
+    remove(&path);
+}
@@ -0,0 +1,91 @@
+use std::ffi::OsStr;
+
+use git_remote_utils::{
+    protocol::Command as CredentialCommand,
+    spawn::{self, CommandOverride, DEFAULT_ENV_ALLOW_LIST},
+};
+use tokio::process::Command;
+
+#[test]
+fn allow_list_matches_names_and_prefixes() {
+    let allowed = |name: &str| spawn::is_allowed(OsStr::new(name), DEFAULT_ENV_ALLOW_LIST);
+    assert!(allowed("PATH"));
+    assert!(allowed("LC_ALL"));
+    assert!(allowed("GIT_CONFIG_GLOBAL"));
+    assert!(!allowed("PATHEXT"));
+    assert!(!allowed("NOTIFY_SOCKET"));
+    assert!(!allowed("AWS_SECRET_ACCESS_KEY"));
+    assert!(spawn::is_allowed(OsStr::new("ANYTHING"), &["*"]));
+}
+
+#[tokio::test]
+async fn child_gets_only_allowed_variables() {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "echo \"$(pwd):${PATH:+path}:${CARGO:-none}\""]);
+    spawn::scrub_env(&mut cmd, DEFAULT_ENV_ALLOW_LIST);
+    let output = cmd.output().await.unwrap();
+    // cargo sets CARGO for the tests it runs
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "/:path:none\n");
+}
+
+#[test]
+fn overrides_name_an_operation() {
+    let o: CommandOverride = "store=pass insert %c".parse().unwrap();
+    assert_eq!(o.command, CredentialCommand::Store);
+    assert_eq!(o.template, "pass insert %c");
+    // only the first `=` separates the operation
+    let o: CommandOverride = "erase=env A=1 git credential %o".parse().unwrap();
+    assert_eq!(o.command, CredentialCommand::Erase);
+    assert_eq!(o.template, "env A=1 git credential %o");
+
+    for (value, error) in [
+        ("git credential fill", "expected OPERATION=COMMAND"),
+        ("fill=git credential fill", "unknown operation \"fill\""),
+        ("get=helper %h", "unknown token in command template: %h"),
+        (
+            "get=helper 100%",
+            "command template ends with an incomplete token",
+        ),
+    ] {
+        let e = value.parse::<CommandOverride>().unwrap_err();
+        assert!(e.contains(error), "{value:?}: {e}");
+    }
+}
+
+#[test]
+fn templates_expand_operation_tokens() {
+    assert_eq!(
+        spawn::expand("git credential %o # %c", CredentialCommand::Get).unwrap(),
+        "git credential fill # get"
+    );
+    assert_eq!(
+        spawn::expand("printf 100%% %o%%", CredentialCommand::Store).unwrap(),
+        "printf 100% approve%"
+    );
+    assert_eq!(
+        spawn::expand("%c%c", CredentialCommand::Erase).unwrap(),
+        "eraseerase"
+    );
+    assert!(spawn::expand("%x", CredentialCommand::Get).is_err());
+    assert!(spawn::expand("%", CredentialCommand::Get).is_err());
+}
+
+#[tokio::test]
+async fn last_override_of_the_operation_is_run() {
+    let overrides: Vec<CommandOverride> =
+        ["get=echo first", "get=echo %c %o 100%%", "store=echo store"]
+            .iter()
+            .map(|o| o.parse().unwrap())
+            .collect();
+    let output = spawn::command(CredentialCommand::Get, &overrides)
+        .unwrap()
+        .output()
+        .await
+        .unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "get fill 100%\n");
+
+    let cmd = spawn::command(CredentialCommand::Erase, &overrides).unwrap();
+    let cmd = cmd.as_std();
+    assert_eq!(cmd.get_program(), "git");
+    assert_eq!(cmd.get_args().collect::<Vec<_>>(), ["credential", "reject"]);
+}
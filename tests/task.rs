@@ -0,0 +1,60 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::future;
+use git_remote_utils::task::TaskSet;
+use tokio::{sync::Notify, time};
+
+#[tokio::test]
+async fn tasks_are_joined_as_they_finish() {
+    let mut tasks = TaskSet::default();
+    assert!(tasks.join_next().await.is_none());
+
+    let notify = Arc::new(Notify::new());
+    let waiting = Arc::clone(&notify);
+    tasks.spawn(async move {
+        waiting.notified().await;
+        2
+    });
+    tasks.spawn(async { 1 });
+    assert_eq!(tasks.len(), 2);
+    assert_eq!(tasks.join_next().await.unwrap().unwrap(), 1);
+    notify.notify_one();
+    assert_eq!(tasks.join_all().await.unwrap(), [2]);
+    assert!(tasks.is_empty());
+}
+
+#[tokio::test]
+async fn tasks_are_aborted_with_the_set() {
+    let dropped = Arc::new(());
+    let mut tasks = TaskSet::default();
+    for _ in 0..3 {
+        let dropped = Arc::clone(&dropped);
+        tasks.spawn(async move {
+            let _dropped = dropped;
+            future::pending::<()>().await;
+        });
+    }
+    drop(tasks);
+
+    time::timeout(Duration::from_secs(5), async {
+        while Arc::strong_count(&dropped) > 1 {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await
+    .expect("tasks outlived their set");
+}
+
+#[tokio::test]
+async fn panics_fail_the_join_but_aborts_do_not() {
+    let mut tasks = TaskSet::default();
+    tasks.spawn(future::pending());
+    tasks.abort_all();
+    assert!(tasks.join_all().await.unwrap().is_empty());
+
+    tasks.spawn(async { panic!("task failed") });
+    tasks.spawn(async {});
+    let e = tasks.join_all().await.unwrap_err();
+    assert!(e.is_panic());
+    assert!(tasks.is_empty());
+}
@@ -0,0 +1,70 @@
+use git_remote_utils::trace::ChromeTrace;
+use serde_json::Value;
+use tracing_subscriber::layer::SubscriberExt as _;
+
+/// Events of the trace, sorted by name, as `(name, row, args)`.
+fn rows(trace: &ChromeTrace) -> Vec<(String, u64, Value)> {
+    let mut json = Vec::new();
+    trace.write(&mut json).unwrap();
+    let json = serde_json::from_slice::<Value>(&json).unwrap();
+    let mut events = json["traceEvents"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|event| {
+            assert_eq!(event["ph"], "X");
+            (
+                event["name"].as_str().unwrap().to_owned(),
+                event["tid"].as_u64().unwrap(),
+                event["args"].clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+    events.sort_by(|a, b| a.0.cmp(&b.0));
+    events
+}
+
+#[test]
+fn concurrent_spans_get_their_own_rows() {
+    let trace = ChromeTrace::default();
+    let subscriber = tracing_subscriber::registry().with(trace.clone());
+    tracing::subscriber::with_default(subscriber, || {
+        let session = tracing::info_span!("session", id = 7).entered();
+        tracing::debug_span!("connect").in_scope(|| {});
+        tracing::debug_span!("handshake").in_scope(|| {});
+        let stdin = tracing::info_span!("stdin");
+        let stdout = tracing::info_span!("stdout");
+        drop((stdin, stdout));
+        drop(session);
+    });
+
+    let events = rows(&trace);
+    let names = events.iter().map(|e| e.0.as_str()).collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        ["connect", "handshake", "session", "stdin", "stdout"]
+    );
+    let row = |name: &str| events.iter().find(|e| e.0 == name).unwrap().1;
+    assert_eq!(row("connect"), row("session"));
+    assert_eq!(row("handshake"), row("session"));
+    assert_eq!(row("stdin"), row("session"));
+    assert_ne!(row("stdout"), row("session"));
+    assert_eq!(events[2].2["id"], 7);
+}
+
+#[test]
+fn open_spans_are_written_as_unfinished() {
+    let trace = ChromeTrace::default();
+    let subscriber = tracing_subscriber::registry().with(trace.clone());
+    let _span = tracing::subscriber::with_default(subscriber, || {
+        let span = tracing::info_span!("session");
+        tracing::info_span!(parent: &span, "transfer").in_scope(|| {});
+        span
+    });
+
+    let events = rows(&trace);
+    assert_eq!(events[0].0, "session");
+    assert_eq!(events[0].2["unfinished"], true);
+    assert_eq!(events[1].0, "transfer");
+    assert_eq!(events[1].2.get("unfinished"), None);
+}
@@ -0,0 +1,103 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use git_remote_utils::transport::{ConnectError, Transport};
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::UnixListener,
+    time,
+};
+
+fn socket_path(name: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("gru-transport-{}-{name}.sock", std::process::id()));
+    remove(&path);
+    path
+}
+
+fn remove(path: &Path) {
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn connects_to_the_socket() {
+    let path = socket_path("connect");
+    let listener = UnixListener::bind(&path).unwrap();
+    let addr = format!("unix:{}", path.display());
+
+    let mut connection = Transport::builder()
+        .timeout(Duration::from_secs(5))
+        .connect(&addr)
+        .await
+        .unwrap();
+    assert!(connection.dial_child.is_none());
+    let (mut server, _) = listener.accept().await.unwrap();
+    connection.write.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+    remove(&path);
+}
+
+#[tokio::test]
+async fn failures_are_retried() {
+    let path = socket_path("retry");
+    let addr = format!("unix:{}", path.display());
+
+    let e = Transport::builder()
+        .retry(2, Duration::from_millis(10))
+        .connect(&addr)
+        .await
+        .unwrap_err();
+    assert!(matches!(e, ConnectError::Connect { .. }), "{e:?}");
+
+    let bind_path = path.clone();
+    let listener = tokio::spawn(async move {
+        time::sleep(Duration::from_millis(100)).await;
+        UnixListener::bind(bind_path).unwrap()
+    });
+    Transport::builder()
+        .retry(100, Duration::from_millis(20))
+        .connect(&addr)
+        .await
+        .unwrap();
+    drop(listener.await.unwrap());
+    remove(&path);
+}
+
+#[tokio::test]
+async fn timeout_includes_retries() {
+    let path = socket_path("timeout");
+    let e = Transport::builder()
+        .timeout(Duration::from_millis(100))
+        .retry(u32::MAX, Duration::from_millis(20))
+        .connect(&format!("unix:{}", path.display()))
+        .await
+        .unwrap_err();
+    assert!(matches!(e, ConnectError::Timeout { .. }), "{e:?}");
+}
+
+#[tokio::test]
+async fn dial_command_is_the_connection() {
+    let mut connection = Transport::builder()
+        .dial_command("echo %h %p; cat")
+        .connect("example.com:22")
+        .await
+        .unwrap();
+    assert!(connection.dial_child.is_some());
+    connection.write.write_all(b"ping").await.unwrap();
+    drop(connection.write);
+    let mut output = String::new();
+    connection.read.read_to_string(&mut output).await.unwrap();
+    assert_eq!(output, "example.com 22\nping");
+
+    let e = Transport::builder()
+        .dial_command("nc %x")
+        .retry(3, Duration::ZERO)
+        .connect("example.com:22")
+        .await
+        .unwrap_err();
+    assert!(matches!(e, ConnectError::Dial { .. }), "{e:?}");
+}